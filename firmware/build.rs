@@ -13,6 +13,10 @@ fn main() {
     gen_hamming(out_dir);
     gen_hann(out_dir);
     gen_blackman(out_dir);
+    gen_blackman_harris(out_dir);
+    gen_flat_top(out_dir);
+    gen_kaiser(out_dir);
+    gen_midi_note_freqs(out_dir);
 
     println!("cargo:rerun-if-changed=build.rs");
 }
@@ -49,29 +53,91 @@ fn gen_fft_sin_table(out_dir: &Path) {
     write_table(&out_dir.join("fft_sin_table.rs"), &table);
 }
 
+/// Shape parameter for the Kaiser window.
+///
+/// Higher values give lower sidelobes but wider peaks (~5 is similar to Hamming, ~8.6 is similar to Blackman).
+const KAISER_BETA: f64 = 6.0;
+
 fn gen_hamming(out_dir: &Path) {
-    write_window_coefficients(&out_dir.join("hamming.rs"), [0.53836, 0.46164, 0., 0.]);
+    write_window_coefficients(&out_dir.join("hamming.rs"), [0.53836, 0.46164, 0., 0., 0.]);
 }
 
 fn gen_hann(out_dir: &Path) {
-    write_window_coefficients(&out_dir.join("hann.rs"), [0.5, 0.5, 0., 0.]);
+    write_window_coefficients(&out_dir.join("hann.rs"), [0.5, 0.5, 0., 0., 0.]);
 }
 
 fn gen_blackman(out_dir: &Path) {
-    write_window_coefficients(&out_dir.join("blackman.rs"), [0.42, 0.5, 0.08, 0.]);
+    write_window_coefficients(&out_dir.join("blackman.rs"), [0.42, 0.5, 0.08, 0., 0.]);
+}
+
+fn gen_blackman_harris(out_dir: &Path) {
+    write_window_coefficients(
+        &out_dir.join("blackman_harris.rs"),
+        [0.35875, 0.48829, 0.14128, 0.01168, 0.],
+    );
+}
+
+fn gen_flat_top(out_dir: &Path) {
+    write_window_coefficients(
+        &out_dir.join("flat_top.rs"),
+        [
+            0.21557895,
+            0.41663158,
+            0.277263158,
+            0.083578947,
+            0.006947368,
+        ],
+    );
+}
+
+fn gen_kaiser(out_dir: &Path) {
+    write_window(&out_dir.join("kaiser.rs"), |x| {
+        // map 0..1 to -1..1, so the window is centered
+        let r = 2.0 * x - 1.0;
+        bessel_i0(KAISER_BETA * f64::sqrt(1.0 - r * r)) / bessel_i0(KAISER_BETA)
+    });
+}
+
+fn gen_midi_note_freqs(out_dir: &Path) {
+    const LEN: usize = 128;
+
+    let table = {
+        let mut table = [0; LEN];
+        for (note, x) in table.iter_mut().enumerate() {
+            // equal temperament, with A4 (note 69) at 440 Hz
+            let freq = 440.0 * f64::powf(2.0, (note as f64 - 69.0) / 12.0);
+            // in millihertz, so low notes are still precise
+            let fixed_point = (1000.0 * freq).round() as u32;
+            *x = fixed_point;
+        }
+        table
+    };
+
+    write_table(&out_dir.join("midi_note_freqs.rs"), &table);
+}
+
+fn write_window_coefficients(file_path: &Path, a: [f64; 5]) {
+    write_window(file_path, |x| {
+        #[rustfmt::skip]
+        let sample = a[0]
+            - a[1] * f64::cos(2.0 * f64::consts::PI * x)
+            + a[2] * f64::cos(4.0 * f64::consts::PI * x)
+            - a[3] * f64::cos(6.0 * f64::consts::PI * x)
+            + a[4] * f64::cos(8.0 * f64::consts::PI * x);
+        sample
+    });
 }
 
-fn write_window_coefficients(file_path: &Path, a: [f64; 4]) {
+/// Write a window table, given a function from position in the window (0..1) to scaling factor (0..=1).
+fn write_window(file_path: &Path, f: impl Fn(f64) -> f64) {
     const LEN: usize = 571;
 
     let table = {
         let mut table = [0; LEN];
         for (i, x) in table.iter_mut().enumerate() {
-            #[rustfmt::skip]
-            let sample = a[0]
-                - a[1] * f64::cos(2.0 * f64::consts::PI * i as f64 / LEN as f64)
-                + a[2] * f64::cos(4.0 * f64::consts::PI * i as f64 / LEN as f64)
-                - a[3] * f64::cos(6.0 * f64::consts::PI * i as f64 / LEN as f64);
+            let sample = f(i as f64 / LEN as f64);
+            // some windows (e.g. flat top) dip slightly below zero, which we can't represent
+            let sample = sample.clamp(0.0, 1.0);
             let fixed_point = (u16::MAX as f64 * sample).round() as u16;
             *x = fixed_point;
         }
@@ -81,6 +147,20 @@ fn write_window_coefficients(file_path: &Path, a: [f64; 4]) {
     write_table(file_path, &table);
 }
 
+/// Zeroth-order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    // power series: sum of ((x/2)^k / k!)^2
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / 2.0 / k).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
 fn write_table<T>(file_path: &Path, table: &[T])
 where
     T: Display + NumericSuffix,
@@ -114,3 +194,7 @@ impl NumericSuffix for u16 {
 impl NumericSuffix for i16 {
     const SUFFIX: &'static str = "i16";
 }
+
+impl NumericSuffix for u32 {
+    const SUFFIX: &'static str = "u32";
+}
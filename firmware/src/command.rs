@@ -0,0 +1,420 @@
+use crate::config;
+use crate::settings::{Param, SetError, Settings};
+use crate::stats;
+use core::fmt::{self, Write};
+use heapless::spsc::Producer;
+use heapless::Vec;
+
+/// A line received on the command port, without its line ending.
+pub type Line = Vec<u8, { config::command::LINE_LEN }>;
+
+/// A command received on the command port.
+///
+/// Commands are lines of space-separated words:
+/// - `help`: list commands
+/// - `list`: list all settings, with their values, units, and ranges
+/// - `get <name>`: show one setting
+/// - `set <name> <value>`: change one setting (`on`/`off` can be used for 1/0)
+/// - `reset`: restore all settings to their defaults
+/// - `stats [reset]`: show the statistics counters, optionally resetting them to zero
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Help,
+    List,
+    Get(Param),
+    Set(Param, u32),
+    Reset,
+    Stats { reset: bool },
+}
+
+/// Why a line couldn't be parsed into a command.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    LineTooLong,
+    UnknownCommand,
+    UnknownSetting,
+    WrongArguments,
+    InvalidValue,
+}
+
+/// Assembles received bytes into lines.
+///
+/// This has no hardware dependencies, so it can be fed bytes from anywhere.
+pub struct LineReader {
+    line: Line,
+    /// Whether the current line was too long, so it will be rejected once it ends
+    overflowed: bool,
+}
+
+impl LineReader {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Feed a single received byte, returning a line if it completes one.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Line, ParseError>> {
+        match byte {
+            b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let overflowed = core::mem::take(&mut self.overflowed);
+                Some(if overflowed {
+                    Err(ParseError::LineTooLong)
+                } else {
+                    Ok(line)
+                })
+            }
+            // allow CRLF line endings
+            b'\r' => None,
+            _ => {
+                if let Err(_) = self.line.push(byte) {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Parse a line into a command, or `None` if it's blank.
+pub fn parse(line: &[u8]) -> Result<Option<Command>, ParseError> {
+    let line = core::str::from_utf8(line).map_err(|_| ParseError::UnknownCommand)?;
+    let mut words = line.split_ascii_whitespace();
+
+    let command = match words.next() {
+        None => return Ok(None),
+        Some("help") => Command::Help,
+        Some("list") => Command::List,
+        Some("get") => Command::Get(parse_param(words.next())?),
+        Some("set") => {
+            let param = parse_param(words.next())?;
+            let value = match words.next() {
+                Some("on") => 1,
+                Some("off") => 0,
+                Some(value) => value.parse().map_err(|_| ParseError::InvalidValue)?,
+                None => return Err(ParseError::WrongArguments),
+            };
+            Command::Set(param, value)
+        }
+        Some("reset") => Command::Reset,
+        Some("stats") => match words.next() {
+            None => Command::Stats { reset: false },
+            Some("reset") => Command::Stats { reset: true },
+            Some(_) => return Err(ParseError::WrongArguments),
+        },
+        Some(_) => return Err(ParseError::UnknownCommand),
+    };
+
+    match words.next() {
+        Some(_) => Err(ParseError::WrongArguments),
+        None => Ok(Some(command)),
+    }
+}
+
+fn parse_param(word: Option<&str>) -> Result<Param, ParseError> {
+    let word = word.ok_or(ParseError::WrongArguments)?;
+    Param::from_name(word).ok_or(ParseError::UnknownSetting)
+}
+
+/// Run a command against the settings, writing its response.
+///
+/// Responses are lines ending with CRLF, so they display correctly in any serial terminal.
+pub fn execute(command: Command, settings: &mut Settings, out: &mut impl Write) -> fmt::Result {
+    match command {
+        Command::Help => {
+            writeln!(
+                out,
+                "commands: help, list, get <name>, set <name> <value>, reset, stats [reset]\r"
+            )
+        }
+        Command::List => {
+            for param in Param::ALL {
+                write_setting(settings, param, out)?;
+            }
+            Ok(())
+        }
+        Command::Get(param) => write_setting(settings, param, out),
+        Command::Set(param, value) => match settings.set(param, value) {
+            Ok(()) => writeln!(out, "ok\r"),
+            Err(SetError::OutOfRange) => {
+                let range = param.range();
+                writeln!(
+                    out,
+                    "error: out of range ({}..={})\r",
+                    range.start(),
+                    range.end()
+                )
+            }
+            Err(SetError::PulseWidthOrder) => writeln!(
+                out,
+                "error: pulse_width_min must be less than pulse_width_max\r"
+            ),
+        },
+        Command::Reset => {
+            *settings = Settings::new();
+            writeln!(out, "ok\r")
+        }
+        Command::Stats { reset } => {
+            let snapshot = if reset {
+                stats::Snapshot::take_and_reset()
+            } else {
+                stats::Snapshot::take()
+            };
+            snapshot.write(out)
+        }
+    }
+}
+
+fn write_setting(settings: &Settings, param: Param, out: &mut impl Write) -> fmt::Result {
+    let range = param.range();
+    writeln!(
+        out,
+        "{} = {} {} ({}..={})\r",
+        param.name(),
+        settings.get(param),
+        param.unit(),
+        range.start(),
+        range.end()
+    )
+}
+
+/// Write the response to a line which couldn't be parsed.
+pub fn write_error(error: ParseError, out: &mut impl Write) -> fmt::Result {
+    let message = match error {
+        ParseError::LineTooLong => "line too long",
+        ParseError::UnknownCommand => "unknown command (try help)",
+        ParseError::UnknownSetting => "unknown setting (try list)",
+        ParseError::WrongArguments => "wrong arguments (try help)",
+        ParseError::InvalidValue => "invalid value",
+    };
+    writeln!(out, "error: {}\r", message)
+}
+
+/// Writes responses into the send queue, waiting for space when it's full.
+///
+/// This must only be used from a lower priority than the task which empties the queue, or it would wait forever.
+pub struct QueueWriter<'a, F, const N: usize> {
+    producer: &'a mut Producer<'static, u8, N>,
+    /// Makes sure the queue is being emptied
+    start_sending: F,
+}
+
+impl<'a, F: FnMut(), const N: usize> QueueWriter<'a, F, N> {
+    pub fn new(producer: &'a mut Producer<'static, u8, N>, start_sending: F) -> Self {
+        Self {
+            producer,
+            start_sending,
+        }
+    }
+}
+
+impl<'a, F: FnMut(), const N: usize> Write for QueueWriter<'a, F, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if !self.producer.ready() {
+                // once started, sending continues until the queue is empty
+                (self.start_sending)();
+                while !self.producer.ready() {}
+            }
+            if let Err(_) = self.producer.enqueue(byte) {
+                return Err(fmt::Error);
+            }
+        }
+        (self.start_sending)();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+
+    fn read_lines(bytes: &[u8]) -> std::vec::Vec<Result<Line, ParseError>> {
+        let mut reader = LineReader::new();
+        bytes.iter().filter_map(|&byte| reader.push(byte)).collect()
+    }
+
+    fn run(line: &str, settings: &mut Settings) -> String {
+        let mut out = String::new();
+        match parse(line.as_bytes()) {
+            Ok(Some(command)) => execute(command, settings, &mut out).unwrap(),
+            Ok(None) => {}
+            Err(e) => write_error(e, &mut out).unwrap(),
+        }
+        out
+    }
+
+    #[test]
+    fn lines() {
+        let lines = read_lines(b"help\nget window\r\n\r\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_deref(), Ok(&b"help"[..]));
+        assert_eq!(lines[1].as_deref(), Ok(&b"get window"[..]));
+        assert_eq!(lines[2].as_deref(), Ok(&b""[..]));
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut bytes = [b'x'; config::command::LINE_LEN + 1].to_vec();
+        bytes.extend_from_slice(b"\nhelp\n");
+        let lines = read_lines(&bytes);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], Err(ParseError::LineTooLong));
+        // the following line is unaffected
+        assert_eq!(lines[1].as_deref(), Ok(&b"help"[..]));
+
+        // exactly the maximum length is fine
+        let mut bytes = [b'x'; config::command::LINE_LEN].to_vec();
+        bytes.push(b'\n');
+        assert!(read_lines(&bytes)[0].is_ok());
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse(b"help"), Ok(Some(Command::Help)));
+        assert_eq!(parse(b"list"), Ok(Some(Command::List)));
+        assert_eq!(parse(b"reset"), Ok(Some(Command::Reset)));
+        assert_eq!(parse(b"stats"), Ok(Some(Command::Stats { reset: false })));
+        assert_eq!(
+            parse(b"stats reset"),
+            Ok(Some(Command::Stats { reset: true }))
+        );
+        assert_eq!(
+            parse(b"get noise_floor"),
+            Ok(Some(Command::Get(Param::NoiseFloor)))
+        );
+        assert_eq!(
+            parse(b"set max_peaks 3"),
+            Ok(Some(Command::Set(Param::MaxPeaks, 3)))
+        );
+        assert_eq!(
+            parse(b"set log_stats on"),
+            Ok(Some(Command::Set(Param::LogStats, 1)))
+        );
+        assert_eq!(
+            parse(b"set log_stats off"),
+            Ok(Some(Command::Set(Param::LogStats, 0)))
+        );
+        // extra whitespace is ignored
+        assert_eq!(
+            parse(b"  set \t window  2 "),
+            Ok(Some(Command::Set(Param::Window, 2)))
+        );
+    }
+
+    #[test]
+    fn parse_blank() {
+        assert_eq!(parse(b""), Ok(None));
+        assert_eq!(parse(b"  \t "), Ok(None));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(parse(b"frobnicate"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse(b"HELP"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse(b"\xFF\xFE"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse(b"get"), Err(ParseError::WrongArguments));
+        assert_eq!(parse(b"get nonsense"), Err(ParseError::UnknownSetting));
+        assert_eq!(parse(b"set"), Err(ParseError::WrongArguments));
+        assert_eq!(parse(b"set window"), Err(ParseError::WrongArguments));
+        assert_eq!(parse(b"set nonsense 1"), Err(ParseError::UnknownSetting));
+        assert_eq!(parse(b"set window 1 2"), Err(ParseError::WrongArguments));
+        assert_eq!(parse(b"help me"), Err(ParseError::WrongArguments));
+        assert_eq!(parse(b"set window -1"), Err(ParseError::InvalidValue));
+        assert_eq!(parse(b"set window 1.5"), Err(ParseError::InvalidValue));
+        assert_eq!(parse(b"set window one"), Err(ParseError::InvalidValue));
+        assert_eq!(
+            parse(b"set window 4294967296"),
+            Err(ParseError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut settings = Settings::new();
+        assert_eq!(run("set noise_floor 1234", &mut settings), "ok\r\n");
+        assert_eq!(settings.noise_floor, 1234);
+        assert!(run("get noise_floor", &mut settings).starts_with("noise_floor = 1234 "));
+    }
+
+    #[test]
+    fn set_out_of_range() {
+        let mut settings = Settings::new();
+        let range = Param::MaxPeaks.range();
+        let response = run(&format!("set max_peaks {}", range.end() + 1), &mut settings);
+        assert_eq!(
+            response,
+            format!(
+                "error: out of range ({}..={})\r\n",
+                range.start(),
+                range.end()
+            )
+        );
+        assert_eq!(settings.max_peaks, Settings::new().max_peaks);
+    }
+
+    #[test]
+    fn set_pulse_width_order() {
+        let mut settings = Settings::new();
+        let min = settings.get(Param::PulseWidthMin);
+        let response = run(&format!("set pulse_width_max {}", min), &mut settings);
+        assert!(response.starts_with("error: pulse_width_min must be less than"));
+        assert_eq!(settings.pulse_width_max, Settings::new().pulse_width_max);
+    }
+
+    #[test]
+    fn malformed_responses() {
+        let mut settings = Settings::new();
+        assert_eq!(
+            run("set window", &mut settings),
+            "error: wrong arguments (try help)\r\n"
+        );
+        assert_eq!(
+            run("get nonsense", &mut settings),
+            "error: unknown setting (try list)\r\n"
+        );
+        assert_eq!(run("", &mut settings), "");
+    }
+
+    #[test]
+    fn list_and_reset() {
+        let mut settings = Settings::new();
+        let list = run("list", &mut settings);
+        let lines: std::vec::Vec<&str> = list.split_terminator('\n').collect();
+        assert_eq!(lines.len(), Param::ALL.len());
+        for (line, param) in lines.into_iter().zip(Param::ALL) {
+            assert!(line.starts_with(param.name()));
+            assert!(line.ends_with('\r'));
+        }
+
+        run("set noise_floor 1234", &mut settings);
+        assert_eq!(run("reset", &mut settings), "ok\r\n");
+        assert_eq!(run("list", &mut settings), list);
+    }
+
+    #[test]
+    fn stats() {
+        let mut settings = Settings::new();
+        assert_eq!(parse(b"stats now"), Err(ParseError::WrongArguments));
+
+        // this is the only test using the global counters
+        stats::PULSES_FIRED.increment();
+        stats::PULSES_FIRED.increment();
+        stats::LATE_FRAMES.increment();
+        let out = run("stats", &mut settings);
+        let lines: std::vec::Vec<&str> = out.split_terminator('\n').collect();
+        assert_eq!(lines.len(), 9);
+        assert!(lines.iter().all(|line| line.ends_with('\r')));
+        assert!(lines.contains(&"pulses_fired = 2\r"));
+        assert!(lines.contains(&"late_frames = 1\r"));
+
+        // `stats` didn't reset them, and `stats reset` shows them before resetting
+        assert_eq!(run("stats reset", &mut settings), out);
+        let out = run("stats", &mut settings);
+        assert!(out.contains("pulses_fired = 0\r"));
+        assert!(out.contains("late_frames = 0\r"));
+    }
+}
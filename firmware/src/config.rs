@@ -5,6 +5,7 @@ pub fn dump_to_log() {
         - FAKE_INPUT_DATA:           {}\n\
         - FAKE_INPUT_CYCLES_PER_BUF: {} ({} Hz)\n\
         - FAKE_INPUT_AMPLITUDE:      {}\n\
+        - CYCLE_WINDOWS: {}\n\
         - LOG_TIMING: {}\n\
         - LOG_CONTROL_VALUES: {}\n\
         - LOG_LAST_FEW_SAMPLES: {}\n\
@@ -12,7 +13,16 @@ pub fn dump_to_log() {
         - LOG_ALL_FFT_AMPLITUDES: {}\n\
         - LOG_FFT_SCRATCH_PEAKS: {}\n\
         - LOG_FFT_PEAKS: {}\n\
+        - LOG_VOICES: {}\n\
         - LOG_ALL_PULSES: {}\n\
+        - LOG_PULSE_COLLISIONS: {}\n\
+        - LOG_PULSE_JITTER: {}\n\
+        - JITTER_HISTOGRAM_BUCKETS:      {}\n\
+        - JITTER_HISTOGRAM_BUCKET_WIDTH: {} ns\n\
+        - LOG_THERMAL_LOAD: {}\n\
+        - LOG_MIDI_MESSAGES: {}\n\
+        - LOG_STATS: {}\n\
+        - LOG_STATS_INTERVAL_SECS: {}\n\
         Clocks:\n\
         - HSE_FREQ: {} Hz\n\
         - SYSCLK:   {} Hz\n\
@@ -28,29 +38,99 @@ pub fn dump_to_log() {
         - BUFFERS_PER_SEC: {}\n\
         - BUF_LEN_RAW:       {} (oversampled)\n\
         - BUF_LEN_PROCESSED: {}\n\
+        - BUF_DURATION: {} us\n\
         FFT:\n\
-        - WINDOW: {}\n\
+        - WINDOW: {} (at startup)\n\
         - BUF_LEN_REAL:         {}\n\
         - BUF_LEN_COMPLEX:      {}\n\
         - BUF_LEN_COMPLEX_REAL: {}\n\
         - FREQ_RESOLUTION: {}.{} Hz\n\
-        - EQUALIZATION: {}\n\
+        - EQUALIZATION: {} (at startup)\n\
+        - MULTI_RESOLUTION: {}\n\
         - MAX_FREQ: {} Hz\n\
-        - MAX_AMPLITUDE: {}\n\
+        - MAX_AMPLITUDE: {} (for startup window)\n\
+        FFT low band:\n\
+        - DECIMATION: {}\n\
+        - CROSSOVER_FREQ: {} Hz\n\
+        - FREQ_RESOLUTION: {}.{} Hz\n\
+        - BUF_LEN: {}\n\
         FFT analysis:\n\
         - MAX_SCRATCH_PEAKS: {}\n\
         - MAX_PEAKS: {}\n\
-        - NOISE_FLOOR_AMPLITUDE: {}\n\
+        - NOISE_FLOOR_AMPLITUDE: {} (at startup)\n\
         Indicator LEDs:\n\
         - PWM_FREQ: {} Hz\n\
+        - SHOW_THERMAL_LOAD: {}\n\
         Pulse generation:\n\
-        - DURATION_RANGE: {}.{} .. {}.{} us\n\
+        - DURATION_RANGE: {}.{} .. {}.{} us (at startup)\n\
+        - OUTPUT_CHANNELS: {}\n\
+        - CHANNEL_ROUTING: {}\n\
+        - CHANNEL_CROSSOVER_FREQS: {} Hz\n\
+        - MAX_VOICES: {}\n\
+        - VOICE_FREQ_TOLERANCE: {}\n\
+        - POLYPHONY: {}\n\
+        - VOICE_PRIORITY: {}\n\
+        - VOICE_HOLD_TIME: {} ms\n\
+        - ARPEGGIATOR: {}\n\
+        - ARP_VOICES: {}\n\
+        - ARP_STEP_TIME: {} ms\n\
+        - ARP_PATTERN: {}\n\
+        - ATTACK:  {} ms\n\
+        - DECAY:   {} ms\n\
+        - SUSTAIN: {}\n\
+        - RELEASE: {} ms\n\
+        - WIDTH_CURVE: {}\n\
+        - COMPENSATION: {}\n\
+        - COMPENSATION_CURVE: {} (Hz, factor)\n\
         - SCHEDULING_OFFSET: {}.{} us\n\
+        - COLLISION_POLICY: {}\n\
+        - COLLISION_WINDOW: {} us\n\
+        - MERGE_WIDENING: {}\n\
+        - TIMELINE_MARGIN: {} us\n\
+        - MAX_SUB_PULSES: {}\n\
+        - SUB_PULSE_SPACING: {} us\n\
+        - MAX_DUTY_CYCLE: {}\n\
+        - DUTY_CYCLE_WINDOW: {} us\n\
+        - MIN_OFF_TIME: {} us\n\
+        - THERMAL_BUDGET: {} us\n\
+        - THERMAL_TIME_CONSTANT: {} ms\n\
+        - THERMAL_DERATE_START: {}\n\
+        - SOFT_START_DURATION: {} ms\n\
+        - SOFT_START_MAX_RATE: {} Hz\n\
+        Operating modes:\n\
+        - MODE: {}\n\
+        - RATE_RANGE: {} .. {} Hz\n\
+        - BURST_PERIOD: {} ms\n\
+        - BURST_ON_RANGE: {} .. {} ms\n\
+        Safety:\n\
+        - WATCHDOG_TIMEOUT: {} ms\n\
+        - STALL_TIMEOUT: {} us\n\
+        - REARM_HOLD: {} ms\n\
+        - REARM_MAX_PULSE_WIDTH: {}.{} us\n\
+        Standby:\n\
+        - ENABLED: {}\n\
+        - TIMEOUT_SECS: {}\n\
+        - SILENCE_AMPLITUDE: {}\n\
+        - WAKE_AMPLITUDE: {}\n\
+        MIDI:\n\
+        - BAUD_RATE: {}\n\
+        - PITCH_BEND_RANGE: {} semitones\n\
+        - RX_QUEUE_LEN: {}\n\
+        - OUTPUT_ENABLED: {}\n\
+        - OUTPUT_CHANNEL: {}\n\
+        - OUTPUT_PITCH_BEND: {}\n\
+        - TX_QUEUE_LEN: {}\n\
+        Command port:\n\
+        - ENABLED: {}\n\
+        - BAUD_RATE: {}\n\
+        - LINE_LEN: {}\n\
+        - TX_QUEUE_LEN: {}\n\
         ",
         debug::FAKE_INPUT_DATA,
         debug::FAKE_INPUT_CYCLES_PER_BUF,
         debug::FAKE_INPUT_CYCLES_PER_BUF * adc::BUFFERS_PER_SEC,
         debug::FAKE_INPUT_AMPLITUDE,
+        debug::CYCLE_WINDOWS,
         debug::LOG_TIMING,
         debug::LOG_CONTROL_VALUES,
         debug::LOG_LAST_FEW_SAMPLES,
@@ -58,7 +138,16 @@ pub fn dump_to_log() {
         debug::LOG_ALL_FFT_AMPLITUDES,
         debug::LOG_FFT_SCRATCH_PEAKS,
         debug::LOG_FFT_PEAKS,
+        debug::LOG_VOICES,
         debug::LOG_ALL_PULSES,
+        debug::LOG_PULSE_COLLISIONS,
+        debug::LOG_PULSE_JITTER,
+        debug::JITTER_HISTOGRAM_BUCKETS,
+        debug::JITTER_HISTOGRAM_BUCKET_WIDTH.to_nanos(),
+        debug::LOG_THERMAL_LOAD,
+        debug::LOG_MIDI_MESSAGES,
+        debug::LOG_STATS,
+        debug::LOG_STATS_INTERVAL_SECS,
         clk::HSE_FREQ.to_Hz(),
         clk::SYSCLK.to_Hz(),
         clk::PCLK1.to_Hz(),
@@ -74,6 +163,7 @@ pub fn dump_to_log() {
         adc::BUFFERS_PER_SEC,
         adc::BUF_LEN_RAW,
         adc::BUF_LEN_PROCESSED,
+        adc::BUF_DURATION.to_micros(),
         fft::WINDOW,
         fft::BUF_LEN_REAL,
         fft::BUF_LEN_COMPLEX,
@@ -81,18 +171,84 @@ pub fn dump_to_log() {
         fft::FREQ_RESOLUTION_X1000 / 1000,
         fft::FREQ_RESOLUTION_X1000 % 1000,
         fft::EQUALIZATION,
+        fft::MULTI_RESOLUTION,
         fft::MAX_FREQ,
-        fft::MAX_AMPLITUDE,
+        fft::max_amplitude(fft::WINDOW),
+        fft::low_band::DECIMATION,
+        fft::low_band::CROSSOVER_FREQ,
+        fft::low_band::FREQ_RESOLUTION_X1000 / 1000,
+        fft::low_band::FREQ_RESOLUTION_X1000 % 1000,
+        fft::low_band::BUF_LEN,
         fft::analysis::MAX_SCRATCH_PEAKS,
         fft::analysis::MAX_PEAKS,
         fft::analysis::NOISE_FLOOR_AMPLITUDE,
         indicator::PWM_FREQ.to_Hz(),
+        indicator::SHOW_THERMAL_LOAD,
         pulse::DURATION_RANGE.start.to_nanos() / 1000,
         pulse::DURATION_RANGE.start.to_nanos() % 1000,
         pulse::DURATION_RANGE.end.to_nanos() / 1000,
         pulse::DURATION_RANGE.end.to_nanos() % 1000,
+        pulse::OUTPUT_CHANNELS,
+        pulse::CHANNEL_ROUTING,
+        pulse::CHANNEL_CROSSOVER_FREQS,
+        pulse::MAX_VOICES,
+        pulse::VOICE_FREQ_TOLERANCE,
+        pulse::POLYPHONY,
+        pulse::VOICE_PRIORITY,
+        pulse::VOICE_HOLD_TIME.to_millis(),
+        pulse::ARPEGGIATOR,
+        pulse::ARP_VOICES,
+        pulse::ARP_STEP_TIME.to_millis(),
+        pulse::ARP_PATTERN,
+        pulse::ATTACK.to_millis(),
+        pulse::DECAY.to_millis(),
+        pulse::SUSTAIN,
+        pulse::RELEASE.to_millis(),
+        pulse::WIDTH_CURVE,
+        pulse::COMPENSATION,
+        pulse::COMPENSATION_CURVE,
         pulse::SCHEDULING_OFFSET.to_nanos() / 1000,
         pulse::SCHEDULING_OFFSET.to_nanos() % 1000,
+        pulse::COLLISION_POLICY,
+        pulse::COLLISION_WINDOW.to_micros(),
+        pulse::MERGE_WIDENING,
+        pulse::TIMELINE_MARGIN.to_micros(),
+        pulse::MAX_SUB_PULSES,
+        pulse::SUB_PULSE_SPACING.to_micros(),
+        pulse::MAX_DUTY_CYCLE,
+        pulse::DUTY_CYCLE_WINDOW.to_micros(),
+        pulse::MIN_OFF_TIME.to_micros(),
+        pulse::THERMAL_BUDGET.to_micros(),
+        pulse::THERMAL_TIME_CONSTANT.to_millis(),
+        pulse::THERMAL_DERATE_START,
+        pulse::SOFT_START_DURATION.to_millis(),
+        pulse::SOFT_START_MAX_RATE,
+        mode::MODE,
+        mode::RATE_RANGE.start,
+        mode::RATE_RANGE.end,
+        mode::BURST_PERIOD.to_millis(),
+        mode::BURST_ON_RANGE.start.to_millis(),
+        mode::BURST_ON_RANGE.end.to_millis(),
+        safety::WATCHDOG_TIMEOUT.to_millis(),
+        safety::STALL_TIMEOUT.to_micros(),
+        safety::REARM_HOLD.to_millis(),
+        safety::REARM_MAX_PULSE_WIDTH.to_nanos() / 1000,
+        safety::REARM_MAX_PULSE_WIDTH.to_nanos() % 1000,
+        standby::ENABLED,
+        standby::TIMEOUT_SECS,
+        standby::SILENCE_AMPLITUDE,
+        standby::WAKE_AMPLITUDE,
+        midi::BAUD_RATE,
+        midi::PITCH_BEND_RANGE,
+        midi::RX_QUEUE_LEN,
+        midi::OUTPUT_ENABLED,
+        midi::OUTPUT_CHANNEL,
+        midi::OUTPUT_PITCH_BEND,
+        midi::TX_QUEUE_LEN,
+        command::ENABLED,
+        command::BAUD_RATE,
+        command::LINE_LEN,
+        command::TX_QUEUE_LEN,
     );
 }
 
@@ -174,6 +330,7 @@ pub mod clk {
 /// ADC configuration
 pub mod adc {
     use crate::config;
+    use crate::time::Duration;
     use stm32f1xx_hal::adc::SampleTime;
 
     /// The resolution of the hardware ADC being used.
@@ -207,11 +364,11 @@ pub mod adc {
     pub(super) const SAMPLES_PER_SEC_RAW_X100: usize =
         100 * config::clk::ADCCLK.to_Hz() as usize / SAMPLE_CYC;
 
-    pub(super) const SAMPLES_PER_SEC_PROCESSED_X100: usize = SAMPLES_PER_SEC_RAW_X100 / OVERSAMPLE;
+    pub const SAMPLES_PER_SEC_PROCESSED_X100: usize = SAMPLES_PER_SEC_RAW_X100 / OVERSAMPLE;
 
     /// Swap buffers ~32 times per second
     /// Note that 1/32 notes (semidemiquavers) at 60 bpm are 1/8 second
-    pub(super) const BUFFERS_PER_SEC: usize = 32;
+    pub const BUFFERS_PER_SEC: usize = 32;
 
     /// Raw, differential, oversampled samples per buffer.
     ///
@@ -231,6 +388,21 @@ pub mod adc {
         BUF_LEN_PROCESSED * OVERSAMPLE == BUF_LEN_RAW,
         "processed buf len should perfectly divide raw buf len"
     );
+
+    /// Exact duration of each buffer.
+    #[allow(clippy::cast_possible_truncation)]
+    pub const BUF_DURATION: Duration = Duration::from_ticks(
+        (BUF_LEN_RAW * SAMPLE_CYC) as u32 * (config::clk::SYSCLK_HZ / config::clk::ADCCLK.to_Hz()),
+    );
+
+    const _: () = assert!(
+        config::clk::SYSCLK_HZ % config::clk::ADCCLK.to_Hz() == 0,
+        "ADCCLK should evenly divide SYSCLK, so buffer duration is exact"
+    );
+    const _: () = assert!(
+        (SAMPLE_CYC_X10_UNADJUSTED + 125) % 10 == 0,
+        "sample cycles should be an integer, so buffer duration is exact"
+    );
 }
 
 /// FFT configuration
@@ -245,9 +417,11 @@ pub mod fft {
     /// Window functions closer to the top have less attenuation and more frequency resolution (sharper peaks),
     /// but more significant sidelobes and noise.
     ///
+    /// All window tables are kept in flash, so the window can be changed at runtime.
+    ///
     /// Amplitudes below are with FAKE_INPUT_CYCLES_PER_BUF=8 and FAKE_INPUT_AMPLITUDE=u16::MAX/2.
     #[allow(dead_code)]
-    #[derive(Format)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
     pub enum Window {
         /// Hard-edged rectangle window.
         ///
@@ -269,9 +443,62 @@ pub mod fft {
         /// Provides some attenuation (amplitude 1400).
         /// Provides slightly wider peaks than Hamming or Hann, but with very good suppression of sidelobes and ringing.
         Blackman,
+        /// 4-term Blackman-Harris window.
+        ///
+        /// Provides more attenuation than Blackman.
+        /// Provides wider peaks than Blackman, but with even lower sidelobes.
+        BlackmanHarris,
+        /// Flat top window.
+        ///
+        /// Provides the most attenuation.
+        /// Provides very wide peaks, but the amplitude of a peak is nearly independent of where it falls between bins,
+        /// so this is the most accurate for measuring amplitude.
+        FlatTop,
+        /// Kaiser window.
+        ///
+        /// Provides attenuation similar to Hann.
+        /// The tradeoff between peak width and sidelobes is set by `KAISER_BETA` in `build.rs`.
+        Kaiser,
+    }
+
+    impl Window {
+        /// All window functions, in declaration order.
+        pub const ALL: [Self; 7] = [
+            Self::Rectangle,
+            Self::Hamming,
+            Self::Hann,
+            Self::Blackman,
+            Self::BlackmanHarris,
+            Self::FlatTop,
+            Self::Kaiser,
+        ];
+
+        /// Name shown on the command port.
+        pub fn name(self) -> &'static str {
+            match self {
+                Self::Rectangle => "rectangle",
+                Self::Hamming => "hamming",
+                Self::Hann => "hann",
+                Self::Blackman => "blackman",
+                Self::BlackmanHarris => "blackman-harris",
+                Self::FlatTop => "flat top",
+                Self::Kaiser => "kaiser",
+            }
+        }
     }
 
-    /// Window type for filtering FFT input
+    const _: () = {
+        let mut i = 0;
+        while i < Window::ALL.len() {
+            assert!(
+                Window::ALL[i] as usize == i,
+                "windows should be in declaration order"
+            );
+            i += 1;
+        }
+    };
+
+    /// Window type for filtering FFT input, used at startup
     pub const WINDOW: Window = Window::Hamming;
 
     /// FFT buffer size should be as large as possible for higher resolution
@@ -294,12 +521,30 @@ pub mod fft {
     /// Whether to amplify high frequencies to offset the FFT's non-flat frequency response
     pub const EQUALIZATION: bool = true;
 
+    /// Whether to run a second FFT over a longer period of decimated samples,
+    /// for finer frequency resolution at low frequencies (see `low_band`).
+    pub const MULTI_RESOLUTION: bool = true;
+
     /// Frequency of the maximum FFT bin
     #[allow(clippy::cast_possible_truncation)]
     pub const MAX_FREQ: u16 = (FREQ_RESOLUTION_X1000 * BUF_LEN_COMPLEX_REAL / 1000) as u16;
 
-    /// Maximum feasible amplitude of an FFT peak.
-    pub const MAX_AMPLITUDE: u16 = {
+    /// Maximum feasible amplitude of an FFT peak, when using the given window function.
+    pub fn max_amplitude(window: Window) -> u16 {
+        MAX_AMPLITUDES[window as usize]
+    }
+
+    const MAX_AMPLITUDES: [u16; Window::ALL.len()] = {
+        let mut amplitudes = [0; Window::ALL.len()];
+        let mut i = 0;
+        while i < Window::ALL.len() {
+            amplitudes[i] = const_max_amplitude(Window::ALL[i]);
+            i += 1;
+        }
+        amplitudes
+    };
+
+    const fn const_max_amplitude(window: Window) -> u16 {
         // samples are scaled up to full i16 range, allowing a potential amplitude of all 16 bits
         let amplitude = u16::MAX;
         // amplitude is scaled down by zeroed padding added to samples
@@ -308,14 +553,52 @@ pub mod fft {
             ScalingFactor::from_ratio(config::adc::BUF_LEN_PROCESSED as u16, BUF_LEN_REAL as u16);
         let amplitude = const_scale_by_u16_u16(amplitude, zeroed_padding_factor);
         // amplitude is scaled down by window function
-        let window_factor = fft::window::amplitude_scale_factor();
+        let window_factor = fft::window::amplitude_scale_factor(window);
         let amplitude = const_scale_by_u16_u16(amplitude, window_factor);
         // for some unexplainable reason, the actual achievable amplitude is a factor of slightly less than 3 off...
         // use a factor of approximately 2*sqrt(2) to provide some safety margin
         let fudge_factor = ScalingFactor::from_ratio(1000, 2828);
         let amplitude = const_scale_by_u16_u16(amplitude, fudge_factor);
         amplitude
-    };
+    }
+
+    /// Multi-resolution analysis, used when `MULTI_RESOLUTION` is enabled.
+    ///
+    /// Since a single FFT has the same bin spacing at all frequencies, it has too little resolution to distinguish bass notes,
+    /// but more than enough at high frequencies.
+    /// So a second FFT of the same size is run over samples from several buffers, decimated to fit into one buffer,
+    /// which provides finer resolution (but a lower maximum frequency, and slower response) for the low band.
+    pub mod low_band {
+        use crate::config;
+
+        /// Number of processed samples averaged into each decimated sample.
+        ///
+        /// The low band FFT covers this many buffers, and has this many times finer frequency resolution.
+        pub const DECIMATION: usize = 4;
+
+        /// Peaks below this frequency are found using the low band FFT, and peaks above using the main FFT.
+        pub const CROSSOVER_FREQ: usize = 300;
+
+        /// Each low band FFT bin is this many Hz apart
+        pub const FREQ_RESOLUTION_X1000: usize = 10 * config::adc::SAMPLES_PER_SEC_PROCESSED_X100
+            / DECIMATION
+            / config::fft::BUF_LEN_REAL;
+
+        /// Number of low band bins to search for peaks (from DC to just below the crossover frequency)
+        pub const SEARCH_LEN: usize = CROSSOVER_FREQ * 1000 / FREQ_RESOLUTION_X1000;
+
+        /// Number of low band bins to keep, including one past the search range, so that peaks at the edge can be refined
+        pub const BUF_LEN: usize = SEARCH_LEN + 1;
+
+        const _: () = assert!(BUF_LEN <= config::fft::BUF_LEN_COMPLEX_REAL);
+
+        /// First main FFT bin to search for peaks (just above the crossover frequency)
+        pub const MAIN_SEARCH_START: usize = {
+            // round up
+            (CROSSOVER_FREQ * 1000 + config::fft::FREQ_RESOLUTION_X1000 - 1)
+                / config::fft::FREQ_RESOLUTION_X1000
+        };
+    }
 
     pub mod analysis {
         use crate::config;
@@ -325,7 +608,13 @@ pub mod fft {
             // Worst case is a zigzag that starts or ends with a peak, e.g. for 3 or 4 buckets
             // . .
             //  . .
-            (config::fft::BUF_LEN_COMPLEX_REAL + 1) / 2
+            let main = (config::fft::BUF_LEN_COMPLEX_REAL + 1) / 2;
+            let low_band = (config::fft::low_band::BUF_LEN + 1) / 2;
+            if config::fft::MULTI_RESOLUTION {
+                main + low_band
+            } else {
+                main
+            }
         };
 
         /// Maximum number of above-threshold peaks to find in the FFT spectrum.
@@ -342,17 +631,187 @@ pub mod indicator {
     use fugit::Hertz;
 
     pub const PWM_FREQ: Hertz<u32> = Hertz::<u32>::kHz(100);
+
+    /// Show the thermal load of the coil driver on the "above threshold" LEDs, instead of peaks above threshold.
+    pub const SHOW_THERMAL_LOAD: bool = false;
 }
 
 /// Pulse generation configuration
 pub mod pulse {
+    use crate::config;
+    use crate::math::ScalingFactor;
     use crate::time::{Duration, PulseDuration};
     use core::ops::Range;
+    use defmt::Format;
 
     /// Pulse duration range when control is set to minimum/maximum
     pub const DURATION_RANGE: Range<PulseDuration> =
         PulseDuration::micros(1)..PulseDuration::micros(10);
 
+    /// Number of pulse outputs in use, on TIM1 channels 1 to 4 (PA8 to PA11 respectively).
+    ///
+    /// Unused outputs are held inactive.
+    ///
+    /// Note that all outputs share the same timer, so pulses on different outputs can't overlap,
+    /// and pulses which collide are fired on all of their outputs at once.
+    pub const OUTPUT_CHANNELS: usize = 1;
+
+    const _: () = assert!(
+        OUTPUT_CHANNELS >= 1 && OUTPUT_CHANNELS <= 4,
+        "TIM1 has 4 output channels"
+    );
+
+    /// Possible ways to route voices to pulse outputs, when there are multiple `OUTPUT_CHANNELS`.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum ChannelRouting {
+        /// Split voices by frequency, at `CHANNEL_CROSSOVER_FREQS`, with the lowest band on channel 1.
+        ByBand,
+        /// Assign each voice to the next channel in turn, when it starts.
+        RoundRobin,
+        /// Fire every voice on all channels.
+        Duplicate,
+    }
+
+    pub const CHANNEL_ROUTING: ChannelRouting = ChannelRouting::ByBand;
+
+    /// Frequencies (in Hz) at or above each crossover are routed to the next channel, with `ChannelRouting::ByBand`.
+    ///
+    /// Only the first `OUTPUT_CHANNELS - 1` crossovers are used.
+    pub const CHANNEL_CROSSOVER_FREQS: [u16; 3] = [300, 1000, 3000];
+
+    /// Maximum number of voices to track at once, including voices which are releasing after their peak disappeared.
+    pub const MAX_VOICES: usize = 2 * config::fft::analysis::MAX_PEAKS;
+
+    /// Peaks within this fraction of a voice's frequency continue that voice, instead of starting a new one.
+    pub const VOICE_FREQ_TOLERANCE: ScalingFactor<u16> = ScalingFactor::from_ratio(3, 100);
+
+    /// Maximum number of held voices which sound at once.
+    ///
+    /// Voices which are releasing after their peak disappeared do not count towards this.
+    pub const POLYPHONY: usize = 3;
+
+    /// Possible ways to choose which voices sound, when there are more voices than `POLYPHONY`.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum VoicePriority {
+        /// Prefer voices with the highest amplitude.
+        Loudest,
+        /// Prefer voices with the highest frequency.
+        Highest,
+        /// Prefer voices with the lowest frequency.
+        Lowest,
+        /// Prefer voices which started earliest.
+        Oldest,
+    }
+
+    pub const VOICE_PRIORITY: VoicePriority = VoicePriority::Loudest;
+
+    /// Newly allocated voices sound for at least this long, regardless of priority,
+    /// so notes don't flicker in and out when peaks of similar priority trade places.
+    pub const VOICE_HOLD_TIME: Duration = Duration::millis(100);
+
+    /// Whether to cycle through the voices of dense chords, instead of playing them all at once.
+    pub const ARPEGGIATOR: bool = false;
+
+    /// Number of voices the arpeggiator plays at a time.
+    ///
+    /// Chords with at most this many voices are played as-is.
+    pub const ARP_VOICES: usize = 1;
+
+    /// Time the arpeggiator plays each step for.
+    ///
+    /// Steps are a whole number of buffers (at least one).
+    pub const ARP_STEP_TIME: Duration = Duration::millis(94);
+
+    /// Possible orders for the arpeggiator to play the voices of a chord in.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum ArpPattern {
+        /// Lowest to highest, then repeat.
+        Up,
+        /// Highest to lowest, then repeat.
+        Down,
+        /// Lowest to highest, then back down.
+        UpDown,
+        /// Randomly chosen steps.
+        Random,
+    }
+
+    pub const ARP_PATTERN: ArpPattern = ArpPattern::Up;
+
+    const _: () = assert!(ARP_VOICES > 0, "arpeggiator should play at least one voice");
+
+    /// Time for a new voice's pulse width to rise to full.
+    ///
+    /// Envelopes are advanced once per buffer, so durations shorter than a buffer are instant.
+    pub const ATTACK: Duration = Duration::millis(60);
+
+    /// Time for a voice's pulse width to fall from full to `SUSTAIN`, after the attack.
+    pub const DECAY: Duration = Duration::millis(120);
+
+    /// Pulse width of a voice while its peak is held, after the attack and decay.
+    pub const SUSTAIN: ScalingFactor<u16> = ScalingFactor::from_ratio(80, 100);
+
+    /// Time for a voice's pulse width to fall from full to zero, after its peak disappears.
+    pub const RELEASE: Duration = Duration::millis(150);
+
+    const _: () = assert!(
+        POLYPHONY <= config::fft::analysis::MAX_PEAKS,
+        "polyphony should not exceed the number of peaks, since each held voice has a peak"
+    );
+    const _: () = assert!(
+        MAX_VOICES > config::fft::analysis::MAX_PEAKS,
+        "there should be more voices than peaks, so that new peaks can always evict a released voice"
+    );
+
+    /// Possible curves mapping peak amplitude to pulse width.
+    ///
+    /// Amplitudes are mapped from `NOISE_FLOOR_AMPLITUDE` (the minimum of `DURATION_RANGE`)
+    /// to the loudest possible peak (the width set by the pulse width control).
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum WidthCurve {
+        /// All pulses are fired at the width set by the pulse width control.
+        Constant,
+        /// Width is proportional to amplitude.
+        Linear,
+        /// Quieter peaks are given relatively wider pulses.
+        Sqrt,
+        /// Quieter peaks are given relatively narrower pulses, emphasizing the loudest parts.
+        Square,
+    }
+
+    pub const WIDTH_CURVE: WidthCurve = WidthCurve::Sqrt;
+
+    /// Whether to scale pulse widths by frequency, via `COMPENSATION_CURVE`.
+    pub const COMPENSATION: bool = true;
+
+    /// Pulse width multipliers by frequency (Hz), interpolated linearly between points and clamped beyond the ends.
+    ///
+    /// At higher frequencies, the same pulse width gives much more average power and a louder sound,
+    /// so this keeps perceived loudness and power roughly constant across the playable range.
+    /// It also acts as a safety curve, limiting on-time at high pulse rates.
+    pub const COMPENSATION_CURVE: [(u16, ScalingFactor<u16>); 6] = [
+        (100, ScalingFactor::ONE),
+        (200, ScalingFactor::ONE),
+        (400, ScalingFactor::from_ratio(70, 100)),
+        (800, ScalingFactor::from_ratio(50, 100)),
+        (1600, ScalingFactor::from_ratio(35, 100)),
+        (3200, ScalingFactor::from_ratio(25, 100)),
+    ];
+
+    const _: () = {
+        let mut i = 1;
+        while i < COMPENSATION_CURVE.len() {
+            assert!(
+                COMPENSATION_CURVE[i - 1].0 < COMPENSATION_CURVE[i].0,
+                "compensation curve should be sorted by frequency"
+            );
+            i += 1;
+        }
+    };
+
     /// Start scheduling pulses this far in the future.
     ///
     /// This ensures that we don't try to schedule a pulse, e.g., just 1 tick after the current time,
@@ -360,4 +819,248 @@ pub mod pulse {
     ///
     /// It also provides a minimum repeat rate, for the same reason.
     pub const SCHEDULING_OFFSET: Duration = Duration::micros(50);
+
+    /// Possible ways to handle pulses from different frequencies which nearly coincide.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum CollisionPolicy {
+        /// Fire a single pulse at the earliest time, widened by `MERGE_WIDENING` for each merged pulse.
+        Merge,
+        /// Fire only the pulse from the loudest frequency, dropping the others.
+        PreferLoudest,
+    }
+
+    pub const COLLISION_POLICY: CollisionPolicy = CollisionPolicy::Merge;
+
+    /// Pulses closer together than this are considered to collide.
+    ///
+    /// This should be at least `MIN_OFF_TIME` plus the maximum pulse width,
+    /// so colliding pulses are handled by the collision policy, instead of being dropped by the limiter.
+    pub const COLLISION_WINDOW: Duration = Duration::micros(110);
+
+    /// When merging colliding pulses, widen the pulse by this fraction of its width for each merged pulse.
+    ///
+    /// Merged pulses are never wider than the maximum of `DURATION_RANGE`.
+    pub const MERGE_WIDENING: ScalingFactor<u16> = ScalingFactor::from_ratio(1, 4);
+
+    const _: () = assert!(
+        COLLISION_WINDOW.ticks() >= SCHEDULING_OFFSET.ticks(),
+        "collision window should be at least the scheduling offset, so merged pulses are never dropped"
+    );
+
+    /// Precomputed pulse timelines extend this far past the end of their buffer.
+    ///
+    /// This keeps pulses firing if the next buffer is swapped in late, e.g. due to higher priority tasks.
+    pub const TIMELINE_MARGIN: Duration = Duration::millis(2);
+
+    /// Maximum number of sub-pulses fired for each pulse, `SUB_PULSE_SPACING` apart, using the TIM1 repetition counter.
+    ///
+    /// Louder voices are fired with more sub-pulses, from 1 up to this, so 1 disables sub-pulses.
+    pub const MAX_SUB_PULSES: u8 = 1;
+
+    /// Time from the start of one sub-pulse to the start of the next.
+    pub const SUB_PULSE_SPACING: PulseDuration = PulseDuration::micros(25);
+
+    const _: () = assert!(MAX_SUB_PULSES >= 1);
+
+    const _: () = assert!(
+        SUB_PULSE_SPACING.ticks() > DURATION_RANGE.end.ticks(),
+        "sub-pulses should not overlap, even at the maximum pulse width"
+    );
+
+    const _: () = assert!(
+        MAX_SUB_PULSES as u32 * SUB_PULSE_SPACING.to_nanos() <= COLLISION_WINDOW.to_nanos(),
+        "sub-pulses should finish before the next pulse, which is at least the collision window later"
+    );
+
+    /// Maximum fraction of time that the output can be on, measured over `DUTY_CYCLE_WINDOW`.
+    ///
+    /// Pulses are shortened or dropped once this is exceeded.
+    pub const MAX_DUTY_CYCLE: ScalingFactor<u16> = ScalingFactor::from_ratio(5, 100);
+
+    /// Sliding window over which the duty cycle is measured.
+    pub const DUTY_CYCLE_WINDOW: Duration = Duration::millis(10);
+
+    /// Minimum time between the end of one pulse and the start of the next.
+    ///
+    /// Pulses are dropped if they would start sooner than this.
+    pub const MIN_OFF_TIME: Duration = Duration::micros(100);
+
+    /// Total pulse on-time which can be accumulated before pulses are suppressed, like an I²t rating.
+    ///
+    /// Accumulated on-time decays exponentially with `THERMAL_TIME_CONSTANT`,
+    /// so the maximum sustained duty cycle is `THERMAL_BUDGET / THERMAL_TIME_CONSTANT`.
+    pub const THERMAL_BUDGET: PulseDuration = PulseDuration::millis(200);
+
+    /// Time constant with which the coil driver cools down.
+    pub const THERMAL_TIME_CONSTANT: Duration = Duration::secs(10);
+
+    /// Fraction of `THERMAL_BUDGET` at which pulse widths start being scaled down,
+    /// reaching zero when the budget is exhausted.
+    pub const THERMAL_DERATE_START: ScalingFactor<u16> = ScalingFactor::from_ratio(1, 2);
+
+    /// After power-up, and after the interlock is re-armed,
+    /// the maximum pulse width ramps up linearly from zero to the control setting over this time.
+    pub const SOFT_START_DURATION: Duration = Duration::secs(2);
+
+    /// If set, the maximum pulse rate (Hz) also ramps up from zero to this over `SOFT_START_DURATION`,
+    /// after which it is no longer limited.
+    pub const SOFT_START_MAX_RATE: Option<u16> = None;
+}
+
+/// Operating mode configuration
+pub mod mode {
+    use crate::time::Duration;
+    use core::ops::Range;
+    use defmt::Format;
+
+    /// Possible ways to generate pulses.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, PartialEq, Eq, Format)]
+    pub enum Mode {
+        /// Pulses follow the peaks of the audio input.
+        Audio,
+        /// Pulses are fired at a fixed rate, set by the threshold control.
+        FixedRate,
+        /// Pulses are fired at a fixed rate, set by the threshold control,
+        /// in bursts every `BURST_PERIOD`, with the on-time set by the pulse width control.
+        Burst,
+        /// Pulses follow notes received over MIDI.
+        Midi,
+    }
+
+    impl Mode {
+        /// All modes, in declaration order.
+        pub const ALL: [Self; 4] = [Self::Audio, Self::FixedRate, Self::Burst, Self::Midi];
+    }
+
+    /// Operating mode to use.
+    ///
+    /// If `None`, the mode is selected at boot from the position of the threshold control,
+    /// with its range divided evenly between the modes in declaration order.
+    pub const MODE: Option<Mode> = Some(Mode::Audio);
+
+    /// Pulse rate range (Hz) when the threshold control is set to minimum/maximum, in the fixed rate and burst modes.
+    pub const RATE_RANGE: Range<u16> = 10..500;
+
+    /// Burst period, in the burst mode.
+    pub const BURST_PERIOD: Duration = Duration::millis(100);
+
+    /// Burst on-time range when the pulse width control is set to minimum/maximum, in the burst mode.
+    ///
+    /// Pulses aren't fired for the rest of each `BURST_PERIOD`.
+    pub const BURST_ON_RANGE: Range<Duration> = Duration::millis(10)..Duration::millis(90);
+
+    const _: () = assert!(
+        BURST_ON_RANGE.end.ticks() < BURST_PERIOD.ticks(),
+        "burst on-time should leave some off-time in each period"
+    );
+}
+
+/// Safety configuration
+pub mod safety {
+    use crate::config;
+    use crate::time::{Duration, PulseDuration};
+    use fugit::MillisDurationU32;
+
+    /// The independent watchdog resets the device if it isn't fed for this long.
+    ///
+    /// It is fed once per buffer while the pipeline is making progress,
+    /// so this should span several buffers, to tolerate the occasional late buffer.
+    pub const WATCHDOG_TIMEOUT: MillisDurationU32 = MillisDurationU32::millis(250);
+
+    /// The pulse scheduler is considered stalled if the next pulse is overdue by this much when buffers are swapped,
+    /// in which case the watchdog is not fed.
+    pub const STALL_TIMEOUT: Duration = Duration::millis(1);
+
+    /// After the interlock closes, it must stay closed for this long before outputs can be re-armed.
+    pub const REARM_HOLD: Duration = Duration::millis(500);
+
+    /// After the interlock closes, outputs are only re-armed once the pulse width control
+    /// has been turned down to this width or less, so output doesn't resume at full power.
+    pub const REARM_MAX_PULSE_WIDTH: PulseDuration = PulseDuration::micros(2);
+
+    const _: () = assert!(
+        REARM_MAX_PULSE_WIDTH.ticks() > config::pulse::DURATION_RANGE.start.ticks(),
+        "pulse width control should be able to reach the re-arm width"
+    );
+}
+
+/// Auto-standby configuration
+pub mod standby {
+    use crate::math::ScalingFactor;
+
+    /// Enter standby when the input is silent, in the audio mode.
+    ///
+    /// No pulses are fired in standby, so noise near the threshold doesn't cause occasional pulses.
+    pub const ENABLED: bool = true;
+
+    /// Enter standby after the input has been silent for this many seconds.
+    pub const TIMEOUT_SECS: usize = 30;
+
+    /// The input is considered silent while its amplitude (as shown on the amplitude indicator) is below this,
+    /// or no peaks are found above the threshold.
+    pub const SILENCE_AMPLITUDE: ScalingFactor<u16> = ScalingFactor::from_ratio(1, 20);
+
+    /// Leave standby once the input amplitude reaches this, and at least one peak is found above the threshold.
+    ///
+    /// This should be higher than `SILENCE_AMPLITUDE`, so noise near it doesn't toggle standby.
+    pub const WAKE_AMPLITUDE: ScalingFactor<u16> = ScalingFactor::from_ratio(1, 10);
+}
+
+/// MIDI configuration
+pub mod midi {
+    /// Serial MIDI baud rate, fixed by the MIDI specification.
+    pub const BAUD_RATE: u32 = 31_250;
+
+    /// Pitch bend range (semitones) when the pitch wheel is at either end.
+    pub const PITCH_BEND_RANGE: u8 = 2;
+
+    /// Number of received messages which can be queued until the next buffer is processed.
+    ///
+    /// At 31250 baud, up to ~32 three-byte messages can be received per buffer (or ~48 with running status).
+    pub const RX_QUEUE_LEN: usize = 64;
+
+    /// Send voices detected in the audio input as MIDI notes, in the audio mode.
+    ///
+    /// Notes start when a voice's peak appears and end when it disappears, with velocity from the peak's amplitude.
+    pub const OUTPUT_ENABLED: bool = false;
+
+    /// Channel (0-15) to send notes on.
+    pub const OUTPUT_CHANNEL: u8 = 0;
+
+    /// Send pitch bend for how far each voice is detuned from its note, using `PITCH_BEND_RANGE`.
+    ///
+    /// Since pitch bend applies to a whole channel, each note is then sent on its own channel,
+    /// starting at `OUTPUT_CHANNEL` (as with MPE), so the receiver should be in a mode which allows that.
+    pub const OUTPUT_PITCH_BEND: bool = false;
+
+    /// Number of bytes which can be queued for sending.
+    ///
+    /// At 31250 baud, ~97 bytes can be sent per buffer.
+    pub const TX_QUEUE_LEN: usize = 128;
+
+    const _: () = assert!(OUTPUT_CHANNEL < 16);
+}
+
+/// Command port configuration
+pub mod command {
+    /// Enable the command port, on USART2 (A2 TX, A3 RX).
+    ///
+    /// HARDWARE CHANGE: enabling this moves the threshold potentiometer input from A2 to A4.
+    /// The schematic (and every existing board) has the potentiometer wired to A2,
+    /// so the board must be rewired (potentiometer wiper to A4, serial adapter to A2/A3) before enabling this.
+    /// Otherwise, the command port drives A2 against the potentiometer, and the threshold control reads a floating pin.
+    pub const ENABLED: bool = false;
+
+    /// Baud rate of the command port, which changes `Settings` at runtime.
+    pub const BAUD_RATE: u32 = 115_200;
+
+    /// Maximum length of a command (bytes), longer commands are rejected.
+    pub const LINE_LEN: usize = 48;
+
+    /// Number of response bytes which can be queued for sending.
+    ///
+    /// Longer responses wait for space, at a lower priority than everything else.
+    pub const TX_QUEUE_LEN: usize = 128;
 }
@@ -3,12 +3,16 @@
 #![allow(clippy::erasing_op)]
 
 use crate::config;
+use crate::time::Duration;
 
 pub const FAKE_INPUT_DATA: bool = false;
 pub const FAKE_INPUT_CYCLES_PER_BUF: usize = 8 /* frequency = this * BUFFERS_PER_SEC */;
 pub const FAKE_INPUT_PHASE: usize = 0 * u16::MAX as usize / 4 /* phase = 2pi * this / u16::MAX */;
 pub const FAKE_INPUT_AMPLITUDE: u16 = config::adc::MAX_POSSIBLE_SAMPLE;
 
+/// Switch to the next window function every second, for comparing them.
+pub const CYCLE_WINDOWS: bool = false;
+
 pub const LOG_TIMING: bool = false;
 
 pub const LOG_CONTROL_VALUES: bool = false;
@@ -21,4 +25,20 @@ pub const LOG_FFT_SCRATCH_PEAKS: bool = false;
 
 pub const LOG_FFT_PEAKS: bool = false;
 
+pub const LOG_VOICES: bool = false;
+
 pub const LOG_ALL_PULSES: bool = false;
+pub const LOG_PULSE_COLLISIONS: bool = false;
+
+/// Log how late pulses fire relative to their scheduled instant, every second.
+pub const LOG_PULSE_JITTER: bool = false;
+pub const JITTER_HISTOGRAM_BUCKETS: usize = 32;
+pub const JITTER_HISTOGRAM_BUCKET_WIDTH: Duration = Duration::micros(1);
+
+pub const LOG_THERMAL_LOAD: bool = false;
+
+pub const LOG_MIDI_MESSAGES: bool = false;
+
+/// Dump runtime statistics and health counters, every `LOG_STATS_INTERVAL_SECS` seconds.
+pub const LOG_STATS: bool = false;
+pub const LOG_STATS_INTERVAL_SECS: usize = 10;
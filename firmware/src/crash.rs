@@ -0,0 +1,283 @@
+use crate::fault::{self, FaultStatus};
+use crate::stats;
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+use defmt::Format;
+use stm32f1xx_hal::pac::RCC;
+
+/// Marks a crash record as written by this firmware, as opposed to random contents after power-on.
+const MAGIC: u32 = 0xC0FF_EE42;
+
+/// What ended the previous run.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    /// No crash was recorded, only the latest statistics.
+    Running,
+    Panic,
+    HardFault,
+}
+
+impl Kind {
+    fn to_raw(self) -> u32 {
+        match self {
+            Self::Running => 0,
+            Self::Panic => 1,
+            Self::HardFault => 2,
+        }
+    }
+
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::Panic,
+            2 => Self::HardFault,
+            _ => Self::Running,
+        }
+    }
+}
+
+/// Location and message of a panic, hashed where necessary to keep the record small.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PanicLocation {
+    file_hash: u32,
+    line: u32,
+    column: u32,
+    message_hash: u32,
+}
+
+/// Post-mortem information about the previous run, which survives a reset.
+///
+/// This is only written as a whole, and only contains plain integers,
+/// so any bit pattern is safe to read back, and the checksum detects partial writes and power-on garbage.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Record {
+    magic: u32,
+    kind: u32,
+    panic: PanicLocation,
+    /// Registers stacked on HardFault: r0, r1, r2, r3, r12, lr, pc, xpsr
+    registers: [u32; 8],
+    /// Fault status registers on HardFault: cfsr, hfsr, mmfar, bfar
+    fault_status: [u32; 4],
+    stats: stats::Snapshot,
+    checksum: u32,
+}
+
+impl Record {
+    const WORDS: usize = core::mem::size_of::<Self>() / 4;
+
+    fn new(kind: Kind) -> Self {
+        Self {
+            magic: MAGIC,
+            kind: kind.to_raw(),
+            panic: PanicLocation {
+                file_hash: 0,
+                line: 0,
+                column: 0,
+                message_hash: 0,
+            },
+            registers: [0; 8],
+            fault_status: [0; 4],
+            stats: stats::Snapshot::take(),
+            checksum: 0,
+        }
+    }
+
+    /// Checksum of all words except the checksum itself.
+    fn compute_checksum(&self) -> u32 {
+        // Safety: `Record` is `repr(C)` and only contains `u32`s
+        let words: &[u32; Self::WORDS] = unsafe { &*addr_of!(*self).cast() };
+        words[..Self::WORDS - 1]
+            .iter()
+            .fold(0, |acc: u32, &word| acc.rotate_left(5) ^ word)
+    }
+}
+
+const _: () = assert!(core::mem::size_of::<Record>() % 4 == 0);
+
+/// Placed in `.uninit`, so it isn't zeroed at startup, and survives a reset (but not loss of power).
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+fn write(mut record: Record) {
+    record.checksum = record.compute_checksum();
+    // Safety: the record is only accessed through these volatile reads and writes,
+    // and partial writes (if one is interrupted by a crash) are detected by the checksum
+    unsafe { addr_of_mut!(RECORD).cast::<Record>().write_volatile(record) };
+}
+
+fn read() -> Option<Record> {
+    // Safety: as above, and any bit pattern is a valid `Record`
+    let record = unsafe { addr_of!(RECORD).cast::<Record>().read_volatile() };
+    (record.magic == MAGIC && record.checksum == record.compute_checksum()).then_some(record)
+}
+
+/// FNV-1a, to hash panic messages and file names.
+struct Hasher(u32);
+
+impl Hasher {
+    fn new() -> Self {
+        Self(0x811C_9DC5)
+    }
+
+    fn hash(s: &str) -> u32 {
+        let mut hasher = Self::new();
+        hasher.write_bytes(s.as_bytes());
+        hasher.0
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u32::from(byte)).wrapping_mul(0x0100_0193);
+        }
+    }
+}
+
+impl core::fmt::Write for Hasher {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Save the latest statistics, so they're available after a reset which isn't caused by a crash (e.g. the watchdog).
+pub fn save_stats() {
+    write(Record::new(Kind::Running));
+}
+
+/// Record a panic, with its location and message if available.
+pub fn record_panic(info: Option<&core::panic::PanicInfo>) {
+    let mut record = Record::new(Kind::Panic);
+    if let Some(info) = info {
+        if let Some(location) = info.location() {
+            record.panic.file_hash = Hasher::hash(location.file());
+            record.panic.line = location.line();
+            record.panic.column = location.column();
+        }
+        let mut hasher = Hasher::new();
+        let _ = core::fmt::Write::write_fmt(&mut hasher, format_args!("{}", info.message()));
+        record.panic.message_hash = hasher.0;
+    }
+    write(record);
+}
+
+/// Whether a panic has been recorded in this run, so a subsequent HardFault (from `udf`) doesn't overwrite it.
+pub fn is_panic_recorded() -> bool {
+    matches!(read(), Some(record) if Kind::from_raw(record.kind) == Kind::Panic)
+}
+
+/// Record a HardFault, with the registers stacked on exception entry, and the fault status registers.
+pub fn record_hard_fault(frame: &cortex_m_rt::ExceptionFrame, status: &FaultStatus) {
+    let mut record = Record::new(Kind::HardFault);
+    record.fault_status = [status.cfsr, status.hfsr, status.mmfar, status.bfar];
+    record.registers = [
+        frame.r0(),
+        frame.r1(),
+        frame.r2(),
+        frame.r3(),
+        frame.r12(),
+        frame.lr(),
+        frame.pc(),
+        frame.xpsr(),
+    ];
+    write(record);
+}
+
+/// Cause of the last reset, from the reset flags.
+#[derive(Copy, Clone, PartialEq, Eq, Format)]
+pub enum ResetCause {
+    LowPower,
+    WindowWatchdog,
+    IndependentWatchdog,
+    Software,
+    PowerOn,
+    Pin,
+    Unknown,
+}
+
+/// Read the cause of the last reset, clearing the reset flags.
+pub fn take_reset_cause() -> ResetCause {
+    // Safety: the reset flags are only accessed here
+    let rcc = unsafe { &*RCC::ptr() };
+    let csr = rcc.csr.read();
+    // in order of precedence, since the pin reset flag is also set by all internal resets
+    let cause = if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.iwdgrstf().bit_is_set() {
+        ResetCause::IndependentWatchdog
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.pinrstf().bit_is_set() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    cause
+}
+
+/// Log the crash record from the previous run (if any), along with the cause of the reset, and clear it.
+pub fn log_and_clear(reset_cause: ResetCause) {
+    let record = match read() {
+        Some(record) => record,
+        None => {
+            defmt::info!("Reset cause: {}, no crash record", reset_cause);
+            return;
+        }
+    };
+
+    match Kind::from_raw(record.kind) {
+        Kind::Panic => {
+            let PanicLocation {
+                file_hash,
+                line,
+                column,
+                message_hash,
+            } = record.panic;
+            defmt::warn!(
+                "Reset cause: {}, after panic at file {=u32:#x} line {} column {}, message {=u32:#x}",
+                reset_cause,
+                file_hash,
+                line,
+                column,
+                message_hash,
+            );
+        }
+        Kind::HardFault => {
+            let [r0, r1, r2, r3, r12, lr, pc, xpsr] = record.registers;
+            let [cfsr, hfsr, mmfar, bfar] = record.fault_status;
+            defmt::warn!(
+                "Reset cause: {}, after HardFault with xpsr={=u32:#x} r0={=u32:#x} r1={=u32:#x} r2={=u32:#x} r3={=u32:#x} r12={=u32:#x}",
+                reset_cause,
+                xpsr,
+                r0,
+                r1,
+                r2,
+                r3,
+                r12,
+            );
+            let status = FaultStatus {
+                cfsr,
+                hfsr,
+                mmfar,
+                bfar,
+            };
+            fault::log_fault(&status, pc, lr);
+        }
+        Kind::Running => {
+            if reset_cause == ResetCause::IndependentWatchdog {
+                defmt::warn!("Reset cause: {}, no crash recorded", reset_cause);
+            } else {
+                defmt::info!("Reset cause: {}, no crash recorded", reset_cause);
+            }
+        }
+    }
+    record.stats.log("Stats before reset");
+
+    // Safety: as above
+    unsafe { addr_of_mut!(RECORD).cast::<u32>().write_volatile(0) };
+}
@@ -0,0 +1,31 @@
+use stm32f1xx_hal::pac::{GPIOA, RCC, TIM1};
+
+/// Force all pulse outputs inactive.
+///
+/// This doesn't rely on any driver state, so it can be called from any context, including panic and fault handlers,
+/// and before the pulse timer has been configured.
+pub fn shutdown_outputs() {
+    // Safety: these are all writes which can only turn outputs off,
+    // so it doesn't matter if they race with the normal pulse driver
+    // (which is never running at the same time, since this is only called when it can't be trusted).
+    unsafe {
+        let rcc = &*RCC::ptr();
+        let tim1 = &*TIM1::ptr();
+        let gpioa = &*GPIOA::ptr();
+
+        // Step 1: stop the timer and disconnect its outputs
+        // (clearing AOE prevents the outputs from being re-enabled by the next update event)
+        tim1.cr1.modify(|_, w| w.cen().clear_bit());
+        tim1.bdtr
+            .modify(|_, w| w.moe().clear_bit().aoe().clear_bit());
+
+        // Step 2: drive PA8-PA11 low as general purpose outputs, so they don't float
+        // (GPIOA might not be clocked yet, e.g. after a reset)
+        rcc.apb2enr.modify(|_, w| w.iopaen().set_bit());
+        gpioa.bsrr.write(|w| w.bits(0b1111 << (16 + 8)));
+        // 0b0010 = general purpose push-pull output, 2 MHz
+        gpioa
+            .crh
+            .modify(|r, w| w.bits((r.bits() & !0xFFFF) | 0x2222));
+    }
+}
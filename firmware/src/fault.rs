@@ -0,0 +1,262 @@
+use defmt::Format;
+
+/// Fault status registers, as read in the fault handler.
+///
+/// Decoding only operates on these plain values, so it can be exercised with synthetic register values.
+#[derive(Copy, Clone)]
+pub struct FaultStatus {
+    /// Configurable Fault Status Register (MMFSR, BFSR, and UFSR)
+    pub cfsr: u32,
+    /// HardFault Status Register
+    pub hfsr: u32,
+    /// MemManage Fault Address Register
+    pub mmfar: u32,
+    /// BusFault Address Register
+    pub bfar: u32,
+}
+
+/// A fault condition flagged in CFSR or HFSR.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Format)]
+pub enum Fault {
+    /// MemManage: instruction fetch from a no-execute region
+    InstructionAccessViolation,
+    /// MemManage: data access to a protected region
+    DataAccessViolation,
+    /// MemManage: on unstacking for an exception return
+    MemManageUnstacking,
+    /// MemManage: on stacking for exception entry
+    MemManageStacking,
+    /// BusFault: on instruction prefetch
+    InstructionBusError,
+    /// BusFault: on data access, with the faulting instruction stacked as PC
+    PreciseDataBusError,
+    /// BusFault: on data access, after the faulting instruction completed
+    ImpreciseDataBusError,
+    /// BusFault: on unstacking for an exception return
+    BusFaultUnstacking,
+    /// BusFault: on stacking for exception entry (e.g. stack overflow)
+    BusFaultStacking,
+    /// UsageFault: undefined instruction (e.g. `udf`)
+    UndefinedInstruction,
+    /// UsageFault: invalid EPSR state (e.g. branch to an address without the thumb bit)
+    InvalidState,
+    /// UsageFault: invalid EXC_RETURN value loaded into PC
+    InvalidPc,
+    /// UsageFault: attempted coprocessor access
+    NoCoprocessor,
+    /// UsageFault: unaligned access (only if trapping is enabled)
+    UnalignedAccess,
+    /// UsageFault: division by zero (only if trapping is enabled)
+    DivideByZero,
+    /// HardFault: on vector table read during exception processing
+    VectorTableRead,
+    /// HardFault: escalated from a configurable fault, which is disabled or couldn't be handled
+    Forced,
+    /// HardFault: debug event
+    DebugEvent,
+}
+
+/// Bits of CFSR flagging each fault.
+const CFSR_FAULTS: [(u32, Fault); 15] = [
+    (1 << 0, Fault::InstructionAccessViolation),
+    (1 << 1, Fault::DataAccessViolation),
+    (1 << 3, Fault::MemManageUnstacking),
+    (1 << 4, Fault::MemManageStacking),
+    (1 << 8, Fault::InstructionBusError),
+    (1 << 9, Fault::PreciseDataBusError),
+    (1 << 10, Fault::ImpreciseDataBusError),
+    (1 << 11, Fault::BusFaultUnstacking),
+    (1 << 12, Fault::BusFaultStacking),
+    (1 << 16, Fault::UndefinedInstruction),
+    (1 << 17, Fault::InvalidState),
+    (1 << 18, Fault::InvalidPc),
+    (1 << 19, Fault::NoCoprocessor),
+    (1 << 24, Fault::UnalignedAccess),
+    (1 << 25, Fault::DivideByZero),
+];
+
+/// Bits of HFSR flagging each fault.
+const HFSR_FAULTS: [(u32, Fault); 3] = [
+    (1 << 1, Fault::VectorTableRead),
+    (1 << 30, Fault::Forced),
+    (1 << 31, Fault::DebugEvent),
+];
+
+/// CFSR.MMARVALID: MMFAR holds the faulting address
+const CFSR_MMARVALID: u32 = 1 << 7;
+/// CFSR.BFARVALID: BFAR holds the faulting address
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+impl FaultStatus {
+    /// Read the fault status registers.
+    pub fn read() -> Self {
+        // Safety: these registers are only read
+        let scb = unsafe { &*cortex_m::peripheral::SCB::PTR };
+        Self {
+            cfsr: scb.cfsr.read(),
+            hfsr: scb.hfsr.read(),
+            mmfar: scb.mmfar.read(),
+            bfar: scb.bfar.read(),
+        }
+    }
+
+    /// All faults flagged, configurable faults first.
+    pub fn faults(&self) -> impl Iterator<Item = Fault> + '_ {
+        let cfsr = CFSR_FAULTS.iter().filter(|&&(bit, _)| self.cfsr & bit != 0);
+        let hfsr = HFSR_FAULTS.iter().filter(|&&(bit, _)| self.hfsr & bit != 0);
+        cfsr.chain(hfsr).map(|&(_, fault)| fault)
+    }
+
+    /// Address whose access caused a MemManage fault, if known.
+    pub fn mem_manage_address(&self) -> Option<u32> {
+        (self.cfsr & CFSR_MMARVALID != 0).then_some(self.mmfar)
+    }
+
+    /// Address whose access caused a BusFault, if known.
+    pub fn bus_fault_address(&self) -> Option<u32> {
+        (self.cfsr & CFSR_BFARVALID != 0).then_some(self.bfar)
+    }
+}
+
+/// Log a decoded fault, given the PC and LR stacked on exception entry.
+pub fn log_fault(status: &FaultStatus, pc: u32, lr: u32) {
+    defmt::error!(
+        "HardFault at pc={=u32:#x} lr={=u32:#x} (cfsr={=u32:#x} hfsr={=u32:#x})",
+        pc,
+        lr,
+        status.cfsr,
+        status.hfsr,
+    );
+    for fault in status.faults() {
+        defmt::error!("- {}", fault);
+    }
+    if let Some(address) = status.mem_manage_address() {
+        defmt::error!("- MemManage fault address: {=u32:#x}", address);
+    }
+    if let Some(address) = status.bus_fault_address() {
+        defmt::error!("- BusFault address: {=u32:#x}", address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(cfsr: u32, hfsr: u32) -> FaultStatus {
+        FaultStatus {
+            cfsr,
+            hfsr,
+            mmfar: 0x2000_1234,
+            bfar: 0x4001_5678,
+        }
+    }
+
+    fn faults(status: &FaultStatus) -> Vec<Fault> {
+        status.faults().collect()
+    }
+
+    /// Fault flagged by each CFSR bit, from the ARMv7-M Architecture Reference Manual (B3.2.15).
+    fn cfsr_fault(bit: u32) -> Option<Fault> {
+        Some(match bit {
+            0 => Fault::InstructionAccessViolation,
+            1 => Fault::DataAccessViolation,
+            3 => Fault::MemManageUnstacking,
+            4 => Fault::MemManageStacking,
+            8 => Fault::InstructionBusError,
+            9 => Fault::PreciseDataBusError,
+            10 => Fault::ImpreciseDataBusError,
+            11 => Fault::BusFaultUnstacking,
+            12 => Fault::BusFaultStacking,
+            16 => Fault::UndefinedInstruction,
+            17 => Fault::InvalidState,
+            18 => Fault::InvalidPc,
+            19 => Fault::NoCoprocessor,
+            24 => Fault::UnalignedAccess,
+            25 => Fault::DivideByZero,
+            // reserved, or an address valid flag
+            _ => return None,
+        })
+    }
+
+    /// Fault flagged by each HFSR bit, from the ARMv7-M Architecture Reference Manual (B3.2.16).
+    fn hfsr_fault(bit: u32) -> Option<Fault> {
+        Some(match bit {
+            1 => Fault::VectorTableRead,
+            30 => Fault::Forced,
+            31 => Fault::DebugEvent,
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn no_faults() {
+        let status = registers(0, 0);
+        assert_eq!(faults(&status), []);
+        assert_eq!(status.mem_manage_address(), None);
+        assert_eq!(status.bus_fault_address(), None);
+    }
+
+    #[test]
+    fn each_cfsr_bit() {
+        for bit in 0..32 {
+            let status = registers(1 << bit, 0);
+            let expected: Vec<Fault> = cfsr_fault(bit).into_iter().collect();
+            assert_eq!(faults(&status), expected, "CFSR bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn each_hfsr_bit() {
+        for bit in 0..32 {
+            let status = registers(0, 1 << bit);
+            let expected: Vec<Fault> = hfsr_fault(bit).into_iter().collect();
+            assert_eq!(faults(&status), expected, "HFSR bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn configurable_faults_come_first() {
+        // a precise bus error escalated to HardFault, as when reading an unmapped address
+        let status = registers((1 << 9) | CFSR_BFARVALID, 1 << 30);
+        assert_eq!(faults(&status), [Fault::PreciseDataBusError, Fault::Forced]);
+
+        // all bits set
+        let status = registers(u32::MAX, u32::MAX);
+        let faults = faults(&status);
+        assert_eq!(faults.len(), CFSR_FAULTS.len() + HFSR_FAULTS.len());
+        assert_eq!(faults[0], Fault::InstructionAccessViolation);
+        assert_eq!(faults[CFSR_FAULTS.len()], Fault::VectorTableRead);
+    }
+
+    #[test]
+    fn mem_manage_address_requires_mmarvalid() {
+        let status = registers(1 << 1, 0);
+        assert_eq!(status.mem_manage_address(), None);
+        assert_eq!(status.bus_fault_address(), None);
+
+        let status = registers((1 << 1) | CFSR_MMARVALID, 0);
+        assert_eq!(status.mem_manage_address(), Some(0x2000_1234));
+        assert_eq!(status.bus_fault_address(), None);
+        assert_eq!(faults(&status), [Fault::DataAccessViolation]);
+    }
+
+    #[test]
+    fn bus_fault_address_requires_bfarvalid() {
+        let status = registers(1 << 9, 0);
+        assert_eq!(status.bus_fault_address(), None);
+        assert_eq!(status.mem_manage_address(), None);
+
+        let status = registers((1 << 9) | CFSR_BFARVALID, 0);
+        assert_eq!(status.bus_fault_address(), Some(0x4001_5678));
+        assert_eq!(status.mem_manage_address(), None);
+        assert_eq!(faults(&status), [Fault::PreciseDataBusError]);
+    }
+
+    #[test]
+    fn address_valid_flags_are_independent() {
+        let status = registers(CFSR_MMARVALID | CFSR_BFARVALID, 0);
+        assert_eq!(status.mem_manage_address(), Some(0x2000_1234));
+        assert_eq!(status.bus_fault_address(), Some(0x4001_5678));
+        assert_eq!(faults(&status), []);
+    }
+}
@@ -7,6 +7,7 @@ use num_complex::Complex;
 pub mod analysis;
 pub mod equalizer;
 mod imp;
+pub mod low_band;
 pub mod window;
 
 /// Run in-place Radix-2 FFT.
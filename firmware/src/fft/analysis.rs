@@ -5,6 +5,7 @@ use crate::math::{
 };
 use crate::panic::OptionalExt;
 use core::num::NonZeroU16;
+use core::ops::Range;
 use fugit::{Duration, Hertz, RateExtU32};
 use heapless::Vec;
 use num_complex::Complex;
@@ -16,6 +17,50 @@ const ONE_HZ: NonZeroU16 = match NonZeroU16::new(1) {
     None => unreachable!(),
 };
 
+/// A range of bins from one FFT to search for peaks.
+pub struct Spectrum<'a> {
+    bins: &'a [Complex<i16>],
+    search: Range<usize>,
+    freq_resolution_x1000: usize,
+    delay: usize,
+}
+
+impl<'a> Spectrum<'a> {
+    /// Create a spectrum to search for peaks.
+    ///
+    /// - `search`: range of bins which can contain peaks, excluding DC (bins outside this range are still used to refine peaks)
+    /// - `freq_resolution_x1000`: frequency difference between adjacent bins
+    /// - `delay`: number of processed samples from the start of the FFT input to the start of the current buffer,
+    ///   used to adjust the phase of peaks to be relative to the current buffer
+    pub fn new(
+        bins: &'a [Complex<i16>],
+        search: Range<usize>,
+        freq_resolution_x1000: usize,
+        delay: usize,
+    ) -> Self {
+        assert!(search.end <= bins.len());
+        Self {
+            bins,
+            search: search.start.max(FIRST_NON_DC_BIN)..search.end,
+            freq_resolution_x1000,
+            delay,
+        }
+    }
+}
+
+/// Find the spectrum containing a scratch peak index, and the index of the bin within that spectrum.
+///
+/// Indexes are assigned to spectra in order, e.g. if the first spectrum has 10 bins, index 10 is bin 0 of the second spectrum.
+fn locate<'s, 'a>(spectra: &'s [Spectrum<'a>], mut i: usize) -> (&'s Spectrum<'a>, usize) {
+    for spectrum in spectra {
+        if i < spectrum.bins.len() {
+            return (spectrum, i);
+        }
+        i -= spectrum.bins.len();
+    }
+    panic!("scratch peak outside of spectra (impossible)")
+}
+
 #[derive(Copy, Clone)]
 pub struct ScratchPeak {
     center: Option<NonZeroU16>,
@@ -24,7 +69,8 @@ pub struct ScratchPeak {
 impl ScratchPeak {
     fn new(center: usize) -> Self {
         const _: () = assert!(
-            config::fft::BUF_LEN_COMPLEX_REAL - 1 <= u16::MAX as usize,
+            config::fft::BUF_LEN_COMPLEX_REAL + config::fft::low_band::BUF_LEN - 1
+                <= u16::MAX as usize,
             "indexes can fit into u16",
         );
         Self {
@@ -32,7 +78,7 @@ impl ScratchPeak {
         }
     }
 
-    // The index of the bin containing the highest amplitude in the peak,
+    // The index of the bin containing the highest amplitude in the peak (see `locate`),
     // or none if the peak has been consumed.
     fn i(self) -> Option<usize> {
         Some(self.center?.get() as usize)
@@ -45,9 +91,11 @@ impl ScratchPeak {
 
 #[inline(never)]
 pub fn find_peaks(
-    bins: &[Complex<i16>; config::fft::BUF_LEN_COMPLEX_REAL],
+    spectra: &[Spectrum],
     scratch_peaks: &mut Vec<ScratchPeak, { config::fft::analysis::MAX_SCRATCH_PEAKS }>,
     amplitude_threshold: control::Sample,
+    noise_floor: u16,
+    max_peaks: usize,
     peaks_out: &mut Vec<Peak, { config::fft::analysis::MAX_PEAKS }>,
 ) {
     // Phase 1: find scratch peaks (all peaks regardless of amplitude)
@@ -66,49 +114,10 @@ pub fn find_peaks(
     {
         scratch_peaks.clear();
 
-        let mut left = FIRST_NON_DC_BIN;
-        loop {
-            if left + 1 >= bins.len() {
-                break;
-            }
-
-            // Step 1: ascend to peak
-            let mut i = left;
-            let mut last_amplitude_squared = amplitude_squared(bins[i]);
-            let center = loop {
-                if i + 1 >= bins.len() {
-                    break i;
-                }
-                let next_amplitude_squared = amplitude_squared(bins[i + 1]);
-                if next_amplitude_squared < last_amplitude_squared {
-                    break i;
-                }
-                last_amplitude_squared = next_amplitude_squared;
-                i += 1;
-            };
-
-            // Step 2: descend to trough
-            let mut i = center;
-            let mut last_amplitude_squared = amplitude_squared(bins[i]);
-            let right = loop {
-                if i + 1 >= bins.len() {
-                    break i;
-                }
-                let next_amplitude_squared = amplitude_squared(bins[i + 1]);
-                if next_amplitude_squared > last_amplitude_squared {
-                    break i;
-                }
-                last_amplitude_squared = next_amplitude_squared;
-                i += 1;
-            };
-
-            // Step 3: append peak
-            scratch_peaks
-                .push(ScratchPeak::new(center))
-                .unwrap_or_else(|_| panic!("too many scratch peaks found (impossible)"));
-
-            // Step 4: left side of next peak is right side of last peak
-            left = right;
+        let mut base = 0;
+        for spectrum in spectra {
+            find_scratch_peaks(spectrum, base, scratch_peaks);
+            base += spectrum.bins.len();
         }
 
         log_scratch_peaks(scratch_peaks);
@@ -118,9 +127,14 @@ pub fn find_peaks(
     {
         peaks_out.clear();
 
+        let amplitude_squared_at = |i| {
+            let (spectrum, i) = locate(spectra, i);
+            amplitude_squared(spectrum.bins[i])
+        };
+
         let mut absolute_highest_amplitude = None;
 
-        for _ in 0..peaks_out.capacity() {
+        for _ in 0..max_peaks.min(peaks_out.capacity()) {
             // Step 1: find highest non-consumed peak
             let mut scratch_iter = scratch_peaks.iter_mut();
             let mut max_peak = match scratch_iter.next() {
@@ -134,7 +148,7 @@ pub fn find_peaks(
                 None => 0,
             };
             let mut max_amplitude_squared = match max_peak.i() {
-                Some(i) => amplitude_squared(bins[i]),
+                Some(i) => amplitude_squared_at(i),
                 // if the first peak has been consumed, set its amplitude to 0 to ensure it'll be overwritten or culled
                 None => 0,
             };
@@ -144,7 +158,7 @@ pub fn find_peaks(
                     // this peak has been consumed
                     None => continue,
                 };
-                let amplitude_squared = amplitude_squared(bins[i]);
+                let amplitude_squared = amplitude_squared_at(i);
                 if amplitude_squared > max_amplitude_squared {
                     max_peak = peak;
                     max_peak_i = i;
@@ -161,7 +175,7 @@ pub fn find_peaks(
             let max_amplitude = amplitude_sqrt(max_amplitude_squared);
 
             // Step 4: cull peaks below noise floor
-            if max_amplitude < config::fft::analysis::NOISE_FLOOR_AMPLITUDE {
+            if max_amplitude < noise_floor {
                 break;
             }
 
@@ -172,9 +186,8 @@ pub fn find_peaks(
                     absolute_highest_amplitude = Some(max_amplitude);
                 }
                 Some(absolute_highest_amplitude) => {
-                    let amplitude_threshold = amplitude_threshold.to_value_in_range(
-                        config::fft::analysis::NOISE_FLOOR_AMPLITUDE..absolute_highest_amplitude,
-                    );
+                    let amplitude_threshold = amplitude_threshold
+                        .to_value_in_range(noise_floor..absolute_highest_amplitude);
 
                     if max_amplitude < amplitude_threshold {
                         break;
@@ -182,7 +195,11 @@ pub fn find_peaks(
                 }
             }
 
-            // Step 6: refine the peak frequency based on shape of the peak
+            // Step 6: look up the spectrum containing the peak (which it will be refined within)
+            let (spectrum, max_peak_i) = locate(spectra, max_peak_i);
+            let bins = spectrum.bins;
+
+            // Step 7: refine the peak frequency based on shape of the peak
             //
             // For example:
             //
@@ -217,7 +234,7 @@ pub fn find_peaks(
             // | .
             // +----->
             let freq = {
-                // Step 6.1: compute amplitudes
+                // Step 7.1: compute amplitudes
                 let center = max_amplitude;
                 let sides = [max_peak_i - 1, max_peak_i + 1].map(|i| match bins.get(i) {
                     Some(bin) => amplitude_sqrt(amplitude_squared(*bin)),
@@ -225,7 +242,7 @@ pub fn find_peaks(
                     None => center,
                 });
 
-                // Step 6.2: determine whether to adjust the frequency positively (right) or negatively (left)
+                // Step 7.2: determine whether to adjust the frequency positively (right) or negatively (left)
                 let is_positive = sides[0] < sides[1];
                 let (small_side, large_side) = if is_positive {
                     (sides[0], sides[1])
@@ -233,13 +250,13 @@ pub fn find_peaks(
                     (sides[1], sides[0])
                 };
 
-                // Step 6.3: normalize amplitudes so the small side is at 0
+                // Step 7.3: normalize amplitudes so the small side is at 0
                 let center = center - small_side;
                 let large_side = large_side - small_side;
                 #[allow(unused_variables)]
                 let small_side = ();
 
-                // Step 6.4: compute adjustment (from 0 to 1/2 of bin resolution)
+                // Step 7.4: compute adjustment (from 0 to 1/2 of bin resolution)
                 // e.g. at this point, we have
                 //   ^
                 // 4 |  .     <- center
@@ -254,10 +271,10 @@ pub fn find_peaks(
                 let center = center + offset;
                 let large_side = large_side + offset;
                 // adjustment = large_side/center * 1/2 * resolution
-                let adjustment_x1000 = large_side * config::fft::FREQ_RESOLUTION_X1000 / center / 2;
+                let adjustment_x1000 = large_side * spectrum.freq_resolution_x1000 / center / 2;
 
-                // Step 6.5: apply adjustment
-                let center_freq_x1000 = max_peak_i * config::fft::FREQ_RESOLUTION_X1000;
+                // Step 7.5: apply adjustment
+                let center_freq_x1000 = max_peak_i * spectrum.freq_resolution_x1000;
                 let real_freq_x1000 = if is_positive {
                     center_freq_x1000 + adjustment_x1000
                 } else {
@@ -269,18 +286,93 @@ pub fn find_peaks(
                 // ensure freq is nonzero
                 let real_freq = NonZeroU16::new(real_freq).unwrap_or(ONE_HZ);
 
-                // Step 6.6: store adjusted frequency
+                // Step 7.6: store adjusted frequency
                 real_freq
             };
 
-            // Step 7: store peak
+            // Step 8: store peak
             peaks_out
-                .push(Peak::from_bin_and_freq(bins[max_peak_i], freq))
+                .push(Peak::from_bin_and_freq(
+                    bins[max_peak_i],
+                    freq,
+                    spectrum.delay,
+                ))
                 .unwrap_or_else(|_| panic!("too many peaks found (impossible)"));
         }
     }
 }
 
+/// Find all peaks in the search range of a spectrum, regardless of amplitude.
+///
+/// `base` is the index of the first bin of this spectrum (see `locate`).
+fn find_scratch_peaks(
+    spectrum: &Spectrum,
+    base: usize,
+    scratch_peaks: &mut Vec<ScratchPeak, { config::fft::analysis::MAX_SCRATCH_PEAKS }>,
+) {
+    let bins = spectrum.bins;
+    let end = spectrum.search.end;
+
+    let mut left = spectrum.search.start;
+    loop {
+        if left + 1 >= end {
+            break;
+        }
+
+        // Step 1: ascend to peak
+        let mut i = left;
+        let mut last_amplitude_squared = amplitude_squared(bins[i]);
+        let center = loop {
+            if i + 1 >= end {
+                break i;
+            }
+            let next_amplitude_squared = amplitude_squared(bins[i + 1]);
+            if next_amplitude_squared < last_amplitude_squared {
+                break i;
+            }
+            last_amplitude_squared = next_amplitude_squared;
+            i += 1;
+        };
+
+        // Step 2: descend to trough
+        let mut i = center;
+        let mut last_amplitude_squared = amplitude_squared(bins[i]);
+        let right = loop {
+            if i + 1 >= end {
+                break i;
+            }
+            let next_amplitude_squared = amplitude_squared(bins[i + 1]);
+            if next_amplitude_squared > last_amplitude_squared {
+                break i;
+            }
+            last_amplitude_squared = next_amplitude_squared;
+            i += 1;
+        };
+
+        // Step 3: skip peaks at the edges of the search range which keep ascending past the edge
+        // (these are just the side of a peak outside the search range, which may be found in another spectrum)
+        let is_edge_of_outside_peak = |edge: usize, outside: usize| {
+            center == edge
+                && outside >= FIRST_NON_DC_BIN
+                && bins.get(outside).map_or(false, |bin| {
+                    amplitude_squared(*bin) > amplitude_squared(bins[center])
+                })
+        };
+        let is_side_of_outside_peak = is_edge_of_outside_peak(spectrum.search.start, center - 1)
+            || is_edge_of_outside_peak(end - 1, center + 1);
+
+        // Step 4: append peak
+        if !is_side_of_outside_peak {
+            scratch_peaks
+                .push(ScratchPeak::new(base + center))
+                .unwrap_or_else(|_| panic!("too many scratch peaks found (impossible)"));
+        }
+
+        // Step 5: left side of next peak is right side of last peak
+        left = right;
+    }
+}
+
 pub fn log_scratch_peaks_prelude() {
     if config::debug::LOG_FFT_SCRATCH_PEAKS {
         defmt::println!(".vz 2 cn FFT Scratch Peaks");
@@ -300,7 +392,10 @@ pub fn log_scratch_peaks(
         let mut is_scratch_peak = [0u8; config::fft::BUF_LEN_COMPLEX_REAL];
         for peak in scratch_peaks.iter() {
             if let Some(i) = peak.i() {
-                is_scratch_peak[i] = 1;
+                // only log peaks from the main spectrum, which is always first
+                if let Some(is_scratch_peak) = is_scratch_peak.get_mut(i) {
+                    *is_scratch_peak = 1;
+                }
             }
         }
         defmt::println!(".vz 2 ys {}", is_scratch_peak);
@@ -308,19 +403,18 @@ pub fn log_scratch_peaks(
 }
 
 pub fn log_peaks(peaks: &[Peak]) {
-    if config::debug::LOG_FFT_PEAKS {
-        for peak in peaks {
-            defmt::println!(
-                "Peak amplitude = {}, freq = {}, phase = {} deg",
-                peak.amplitude(),
-                peak.freq().to_Hz(),
-                360.scale_by(peak.phase()),
-            );
-        }
+    for peak in peaks {
+        defmt::println!(
+            "Peak amplitude = {}, freq = {}, phase = {} deg",
+            peak.amplitude(),
+            peak.freq().to_Hz(),
+            360.scale_by(peak.phase()),
+        );
     }
 }
 
 /// Represents one peak frequency from the FFT, with frequency and scale factor
+#[derive(Copy, Clone)]
 pub struct Peak {
     amplitude: u16,
     freq: NonZeroU16,
@@ -328,9 +422,20 @@ pub struct Peak {
 }
 
 impl Peak {
-    fn from_bin_and_freq(bin: Complex<i16>, freq: NonZeroU16) -> Self {
+    fn from_bin_and_freq(bin: Complex<i16>, freq: NonZeroU16, delay: usize) -> Self {
         let amplitude = amplitude_sqrt(amplitude_squared(bin));
         let phase = phase(bin);
+        // shift phase forward by the number of cycles between the start of the FFT input and the current buffer
+        let phase = if delay == 0 {
+            phase
+        } else {
+            let delay_cycles_x65536 = u64::from(freq.get()) * delay as u64 * 65536 * 100
+                / config::adc::SAMPLES_PER_SEC_PROCESSED_X100 as u64;
+            // only the fractional part of the cycles matters
+            #[allow(clippy::cast_possible_truncation)]
+            let delay_phase = ScalingFactor::from_raw((delay_cycles_x65536 % 65536) as u16);
+            phase.wrapping_add(delay_phase)
+        };
         Self {
             amplitude,
             freq,
@@ -354,6 +459,16 @@ impl Peak {
         self.phase
     }
 
+    /// Shift the phase forward by a duration, as if the peak continued at the same frequency.
+    pub fn advance<const DENOM: u32>(&mut self, by: Duration<u32, 1, DENOM>) {
+        let cycles_x65536 =
+            u64::from(self.freq.get()) * u64::from(by.ticks()) * 65536 / u64::from(DENOM);
+        // only the fractional part of the cycles matters
+        #[allow(clippy::cast_possible_truncation)]
+        let phase = ScalingFactor::from_raw((cycles_x65536 % 65536) as u16);
+        self.phase = self.phase.wrapping_add(phase);
+    }
+
     pub fn phase_offset<const DENOM: u32>(&self) -> Duration<u32, 1, DENOM> {
         let period_ticks = self.period::<DENOM>().ticks();
         let phase_offset_ticks = period_ticks.scale_by(self.phase);
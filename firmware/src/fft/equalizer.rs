@@ -7,10 +7,10 @@ use num_complex::Complex;
 ///
 /// This probably shouldn't be necessary?
 #[inline(never)]
-pub fn apply_to(bins: &mut [Complex<i16>; config::fft::BUF_LEN_COMPLEX_REAL]) {
+pub fn apply_to(bins: &mut [Complex<i16>; config::fft::BUF_LEN_COMPLEX_REAL], max_amplitude: u16) {
     for (i, bin) in bins.iter_mut().enumerate() {
         // The frequency response of the FFT is decently approximated by a line
-        // passing through (0, MAX_AMPLITUDE) and (MAX_FREQ, 0).
+        // passing through (0, max_amplitude) and (MAX_FREQ, 0).
 
         // Scale amplitudes in order to invert this non-flat frequency response,
         // by effectively multiplying each sample by `1/<amplitude for ideal signal at this freq>`.
@@ -30,15 +30,15 @@ pub fn apply_to(bins: &mut [Complex<i16>; config::fft::BUF_LEN_COMPLEX_REAL]) {
         // even at middle frequencies.
         let range_compression = ScalingFactor::from_ratio(7, 8);
 
-        // Compute the response, starting with MAX_AMPLITUDE at 0 Hz, and decreasing as we get closer to MAX_FREQ.
-        let freq_response_at_this_point = config::fft::MAX_AMPLITUDE
-            - config::fft::MAX_AMPLITUDE
+        // Compute the response, starting with max_amplitude at 0 Hz, and decreasing as we get closer to MAX_FREQ.
+        let freq_response_at_this_point = max_amplitude
+            - max_amplitude
                 .scale_by(progress_in_freq_range)
                 .scale_by(range_compression);
 
         let apply_scaling = |x| {
             // Step 2: prescale by max potential divisor, to avoid reducing overall amplitude
-            let prescaled: i32 = i32::from(x) * i32::from(config::fft::MAX_AMPLITUDE);
+            let prescaled: i32 = i32::from(x) * i32::from(max_amplitude);
 
             // Step 3: scale down by frequency response at this point
             let downscaled: i32 = prescaled / i32::from(freq_response_at_this_point);
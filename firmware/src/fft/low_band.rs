@@ -0,0 +1,110 @@
+use crate::adc;
+use crate::config;
+use crate::config::fft::Window;
+use crate::fft;
+use crate::math::{DivRound, Truncate};
+use crate::panic::OptionalExt;
+use num_complex::Complex;
+
+const LEN: usize = config::adc::BUF_LEN_PROCESSED;
+
+/// History of decimated samples, covering the last `DECIMATION` buffers.
+pub struct History {
+    /// Ring buffer of decimated samples
+    samples: [i16; LEN],
+    /// Index of the oldest sample, which will be overwritten next
+    oldest: usize,
+    /// Sum of processed samples which have not been decimated yet
+    pending_sum: i32,
+    /// Number of processed samples which have not been decimated yet
+    pending_len: usize,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self {
+            samples: [0; LEN],
+            oldest: 0,
+            pending_sum: 0,
+            pending_len: 0,
+        }
+    }
+
+    /// Decimate a buffer of processed samples and add it to the history.
+    #[inline(never)]
+    fn push(&mut self, values: &[i16; LEN]) {
+        let decimation: i32 = config::fft::low_band::DECIMATION
+            .try_into()
+            .unwrap_infallible();
+
+        for &value in values {
+            self.pending_sum += i32::from(value);
+            self.pending_len += 1;
+
+            if self.pending_len == config::fft::low_band::DECIMATION {
+                // average of samples should fit back into i16
+                self.samples[self.oldest] = self.pending_sum.div_round(decimation).truncate();
+                self.oldest += 1;
+                if self.oldest == LEN {
+                    self.oldest = 0;
+                }
+                self.pending_sum = 0;
+                self.pending_len = 0;
+            }
+        }
+    }
+
+    /// Copy decimated samples into `values`, oldest first.
+    fn copy_to(&self, values: &mut [i16; LEN]) {
+        let (newer, older) = self.samples.split_at(self.oldest);
+        let (values_older, values_newer) = values.split_at_mut(older.len());
+        values_older.copy_from_slice(older);
+        values_newer.copy_from_slice(newer);
+    }
+
+    /// Number of processed samples from the start of the history to the start of the current buffer.
+    pub fn delay(&self) -> usize {
+        // the history ends before any samples which haven't been decimated yet
+        let history_len = LEN * config::fft::low_band::DECIMATION;
+        history_len + self.pending_len - LEN
+    }
+}
+
+/// Add raw samples to the decimated history, then run the low band FFT over the history,
+/// storing the low band bins in `bins_out`.
+///
+/// Uses the same window function, and the same size FFT, as the main FFT, so amplitudes are comparable.
+#[inline(never)]
+pub fn run(
+    samples: &[u16; config::adc::BUF_LEN_RAW],
+    history: &mut History,
+    window: Window,
+    equalization: bool,
+    scratch: &mut [i16; config::fft::BUF_LEN_REAL],
+    bins_out: &mut [Complex<i16>; config::fft::low_band::BUF_LEN],
+) {
+    let (values, padding) = scratch.split_at_mut(LEN);
+    let values: &mut [_; LEN] = values.try_into().unwrap_infallible();
+
+    // Step 1: add samples to history
+    adc::process_raw_samples(samples, values);
+    history.push(values);
+
+    // Step 2: populate values and padding in FFT scratch buffer
+    history.copy_to(values);
+    padding.fill(0);
+
+    // Step 3: apply window function and scaling to data
+    fft::window::apply_with_scaling(window, values);
+
+    // Step 4: run fft
+    let bins = fft::run(scratch);
+
+    if equalization {
+        // Step 5: run equalizer
+        fft::equalizer::apply_to(bins, config::fft::max_amplitude(window));
+    }
+
+    // Step 6: keep only the low band
+    bins_out.copy_from_slice(&bins[..config::fft::low_band::BUF_LEN]);
+}
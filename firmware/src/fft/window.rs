@@ -1,4 +1,5 @@
 use crate::config;
+use crate::config::fft::Window;
 use crate::math::{ScaleBy, ScalingFactor};
 
 // put in RAM: ~100us improvement
@@ -15,14 +16,30 @@ const HANN: &[u16; config::adc::BUF_LEN_PROCESSED] =
 const BLACKMAN: &[u16; config::adc::BUF_LEN_PROCESSED] =
     &include!(concat!(env!("OUT_DIR"), "/blackman.rs"));
 
+const BLACKMAN_HARRIS: &[u16; config::adc::BUF_LEN_PROCESSED] =
+    &include!(concat!(env!("OUT_DIR"), "/blackman_harris.rs"));
+
+const FLAT_TOP: &[u16; config::adc::BUF_LEN_PROCESSED] =
+    &include!(concat!(env!("OUT_DIR"), "/flat_top.rs"));
+
+const KAISER: &[u16; config::adc::BUF_LEN_PROCESSED] =
+    &include!(concat!(env!("OUT_DIR"), "/kaiser.rs"));
+
+const fn table(window: Window) -> &'static [u16; config::adc::BUF_LEN_PROCESSED] {
+    match window {
+        Window::Rectangle => RECTANGLE,
+        Window::Hamming => HAMMING,
+        Window::Hann => HANN,
+        Window::Blackman => BLACKMAN,
+        Window::BlackmanHarris => BLACKMAN_HARRIS,
+        Window::FlatTop => FLAT_TOP,
+        Window::Kaiser => KAISER,
+    }
+}
+
 #[inline(never)]
-pub fn apply_with_scaling(data: &mut [i16; config::adc::BUF_LEN_PROCESSED]) {
-    let window = match config::fft::WINDOW {
-        config::fft::Window::Rectangle => RECTANGLE,
-        config::fft::Window::Hamming => HAMMING,
-        config::fft::Window::Hann => HANN,
-        config::fft::Window::Blackman => BLACKMAN,
-    };
+pub fn apply_with_scaling(window: Window, data: &mut [i16; config::adc::BUF_LEN_PROCESSED]) {
+    let window = table(window);
 
     assert_eq!(data.len(), window.len());
 
@@ -37,16 +54,13 @@ pub fn apply_with_scaling(data: &mut [i16; config::adc::BUF_LEN_PROCESSED]) {
     }
 }
 
-pub const fn amplitude_scale_factor() -> ScalingFactor<u16> {
-    let window = match config::fft::WINDOW {
-        config::fft::Window::Rectangle => {
-            // no scaling
-            return ScalingFactor::ONE;
-        }
-        config::fft::Window::Hamming => HAMMING,
-        config::fft::Window::Hann => HANN,
-        config::fft::Window::Blackman => BLACKMAN,
-    };
+pub const fn amplitude_scale_factor(window: Window) -> ScalingFactor<u16> {
+    if let Window::Rectangle = window {
+        // no scaling
+        return ScalingFactor::ONE;
+    }
+
+    let window = table(window);
 
     let mut sum_of_factors: u32 = 0;
 
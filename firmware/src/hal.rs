@@ -2,15 +2,42 @@
 
 pub mod tim;
 
+use crate::panic::OptionalExt;
+use stm32f1xx_hal::adc::Adc;
+use stm32f1xx_hal::pac::ADC2;
+use stm32f1xx_hal::prelude::*;
+
+/// Threshold potentiometer, on whichever pin it's connected to.
+pub enum ThresholdControl {
+    /// Original boards
+    A2(pins::A2_ADC2C2),
+    /// Boards with the command port, which uses A2
+    A4(pins::A4_ADC2C4),
+}
+
+impl ThresholdControl {
+    pub fn read(&mut self, adc: &mut Adc<ADC2>) -> u16 {
+        match self {
+            Self::A2(pin) => adc.read(pin),
+            Self::A4(pin) => adc.read(pin),
+        }
+        .unwrap_infallible()
+    }
+}
+
 #[allow(non_camel_case_types)]
 pub mod pins {
-    use stm32f1xx_hal::gpio::{Alternate, Analog, Output, Pin, PushPull, CRH, CRL};
+    use stm32f1xx_hal::gpio::{
+        Alternate, Analog, Floating, Input, Output, Pin, PullUp, PushPull, CRH, CRL,
+    };
 
     /// Amplified single-ended audio input
     pub type A0_ADC1C0 = Pin<Analog, CRL, 'A', 0>;
 
     /// Threshold potentiometer
     pub type A2_ADC2C2 = Pin<Analog, CRL, 'A', 2>;
+    /// Threshold potentiometer, if the command port is enabled (which uses A2)
+    pub type A4_ADC2C4 = Pin<Analog, CRL, 'A', 4>;
 
     /// Pulse width potentiometer
     pub type A1_ADC2C1 = Pin<Analog, CRL, 'A', 1>;
@@ -33,8 +60,27 @@ pub mod pins {
     /// Above threshold indicator LED 4
     pub type B9_TIM4C4 = Pin<Alternate<PushPull>, CRH, 'B', 9>;
 
-    /// Pulse output
+    /// Pulse output 1
     pub type A8_TIM1C1_PULSE = Pin<Alternate<PushPull>, CRH, 'A', 8>;
+    /// Pulse output 2
+    pub type A9_TIM1C2_PULSE = Pin<Alternate<PushPull>, CRH, 'A', 9>;
+    /// Pulse output 3
+    pub type A10_TIM1C3_PULSE = Pin<Alternate<PushPull>, CRH, 'A', 10>;
+    /// Pulse output 4
+    pub type A11_TIM1C4_PULSE = Pin<Alternate<PushPull>, CRH, 'A', 11>;
+
+    /// Interlock input, also the pulse timer break input (closed = shorted to ground)
+    pub type B12_TIM1_BKIN_INTERLOCK = Pin<Input<PullUp>, CRH, 'B', 12>;
+
+    /// Command port output (only if enabled, see `config::command::ENABLED`)
+    pub type A2_USART2_TX_COMMAND = Pin<Alternate<PushPull>, CRL, 'A', 2>;
+    /// Command port input (only if enabled)
+    pub type A3_USART2_RX_COMMAND = Pin<Input<Floating>, CRL, 'A', 3>;
+
+    /// MIDI output (to DIN via a buffer)
+    pub type B10_USART3_TX_MIDI_OUT = Pin<Alternate<PushPull>, CRH, 'B', 10>;
+    /// MIDI input (from DIN via an optocoupler)
+    pub type B11_USART3_RX_MIDI_IN = Pin<Input<Floating>, CRH, 'B', 11>;
 
     /// Debug LED output
     pub type C13_DEBUG_LED = Pin<Output<PushPull>, CRH, 'C', 13>;
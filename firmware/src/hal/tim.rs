@@ -36,6 +36,55 @@ impl<TIM: Instance, const FREQ: u32> OneshotTimer<TIM, FREQ> {
     }
 }
 
+/// A set of timer channels, as a bitmask with bit 0 representing channel 1.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Channels(u8);
+
+impl Channels {
+    pub const NONE: Self = Self(0);
+
+    /// The single channel with the given index (0 for channel 1).
+    pub const fn single(i: usize) -> Self {
+        assert!(i < 4);
+        Self(1 << i)
+    }
+
+    /// The first `n` channels.
+    pub const fn first(n: usize) -> Self {
+        assert!(n <= 4);
+        Self((1 << n) - 1)
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, i: usize) -> bool {
+        self.0 & (1 << i) != 0
+    }
+}
+
+/// Repetition of a pulse, to fire a train of pulses from a single trigger.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Repeat<const FREQ: u32> {
+    /// Number of pulses to fire, at most 256 (0 or 1 fires a single pulse)
+    pub count: u8,
+    /// Time from the start of one pulse to the start of the next
+    pub spacing: Duration<u32, 1, FREQ>,
+}
+
+impl<const FREQ: u32> Repeat<FREQ> {
+    /// Time from starting the timer until the last pulse ends, given the width of each pulse.
+    pub fn length(&self, pulse_time: Duration<u32, 1, FREQ>) -> Duration<u32, 1, FREQ> {
+        if self.count > 1 {
+            // every pulse is at the end of its period
+            self.spacing * u32::from(self.count)
+        } else {
+            pulse_time
+        }
+    }
+}
+
 pub struct OnePulse<TIM, REMAP, P, PINS, const FREQ: u32>
 where
     TIM: Instance,
@@ -114,6 +163,12 @@ impl<const FREQ: u32> OneshotTimer<TIM1, FREQ> {
         // Set automatic output enable (for TIM1 only)
         self.tim.bdtr.modify(|_, w| w.aoe().set_bit());
 
+        // Enable the break input (active high), which asynchronously clears MOE, forcing all outputs inactive
+        // (this requires the BKIN pin to be configured as an input)
+        self.tim
+            .bdtr
+            .modify(|_, w| w.bke().set_bit().bkp().set_bit());
+
         OnePulse {
             timer: self,
             _pins: PhantomData,
@@ -126,21 +181,81 @@ where
     REMAP: Remap<Periph = TIM1>,
     PINS: Pins<REMAP, P>,
 {
-    pub fn fire(&mut self, pulse_time: Duration<u32, 1, FREQ>) {
-        // time is ARR - CCR + 1, so subtract 1 tick
-        // (note that CCR is effectively 0 here due to fast enable)
-        self.timer.tim.arr.write(|w| {
-            w.arr().bits({
-                let ticks = pulse_time.ticks() - 1;
-                assert!(ticks > 0);
-                ticks.try_into().unwrap()
-            })
+    /// Fire a pulse on the given channels, holding the other channels inactive.
+    ///
+    /// With `repeat.count > 1`, this fires a train of pulses `repeat.spacing` apart, using the repetition counter,
+    /// so no further interrupts are needed.
+    pub fn fire(
+        &mut self,
+        pulse_time: Duration<u32, 1, FREQ>,
+        channels: Channels,
+        repeat: Repeat<FREQ>,
+    ) {
+        let ticks = pulse_time.ticks();
+        let (arr, ccr, fast) = if repeat.count > 1 {
+            // each period is inactive until CCR, then active until ARR,
+            // so without fast enable, every pulse is at the end of its period
+            let spacing = repeat.spacing.ticks();
+            assert!(spacing > ticks);
+            (spacing - 1, spacing - ticks, false)
+        } else {
+            // time is ARR - CCR + 1, so subtract 1 tick
+            // (note that CCR is effectively 0 here due to fast enable)
+            let ticks = ticks - 1;
+            assert!(ticks > 0);
+            (ticks, 1, true)
+        };
+
+        let tim = &self.timer.tim;
+
+        // route the pulse to the selected channels
+        // (the output control mode is not preloaded, so this takes effect immediately,
+        //  and channels without pins are never enabled, so their mode doesn't matter)
+        let mode = |i| {
+            if channels.contains(i) {
+                Ocm::PwmMode2
+            } else {
+                Ocm::ForceInactive
+            }
+        };
+        tim.ccmr1_output().modify(|_, w| {
+            w.oc1m()
+                .bits(mode(0) as _)
+                .oc1fe()
+                .bit(fast)
+                .oc2m()
+                .bits(mode(1) as _)
+                .oc2fe()
+                .bit(fast)
+        });
+        tim.ccmr2_output().modify(|_, w| {
+            w.oc3m()
+                .bits(mode(2) as _)
+                .oc3fe()
+                .bit(fast)
+                .oc4m()
+                .bits(mode(3) as _)
+                .oc4fe()
+                .bit(fast)
         });
 
+        let ccr: u16 = ccr.try_into().unwrap();
+        tim.arr.write(|w| w.arr().bits(arr.try_into().unwrap()));
+        tim.ccr1.write(|w| w.ccr().bits(ccr));
+        tim.ccr2.write(|w| w.ccr().bits(ccr));
+        tim.ccr3.write(|w| w.ccr().bits(ccr));
+        tim.ccr4.write(|w| w.ccr().bits(ccr));
+        // SAFETY: REP is 8 bits wide, so any u8 is a valid RCR value
+        tim.rcr
+            .write(|w| unsafe { w.rep().bits(repeat.count.max(1) - 1) });
+
+        // Trigger update event to load the preloaded CCR and repetition counter
+        // (also sets the URS bit to prevent the UG bit from setting the update flag)
+        tim.cr1.modify(|_, w| w.urs().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+
         // enable one pulse mode and start the timer
-        self.timer
-            .tim
-            .cr1
-            .write(|w| w.opm().set_bit().cen().set_bit());
+        // (also clears the URS bit)
+        tim.cr1.write(|w| w.opm().set_bit().cen().set_bit());
     }
 }
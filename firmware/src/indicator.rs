@@ -7,9 +7,11 @@ use heapless::Vec;
 /// Number of indicators to distribute the scaling factor between
 const N: usize = 4;
 
-/// Compute scaling factors for amplitude indicator, based on raw ADC samples.
+/// Compute overall amplitude, based on raw ADC samples.
+///
+/// Use `ScalingFactor::distribute` to get scaling factors for the amplitude indicator.
 #[inline(never)]
-pub fn amplitude(input: &[u16; config::adc::BUF_LEN_RAW]) -> [ScalingFactor<u16>; N] {
+pub fn amplitude(input: &[u16; config::adc::BUF_LEN_RAW]) -> ScalingFactor<u16> {
     // Step 1: find min and max samples
 
     let mut min_sample = u16::MAX;
@@ -48,9 +50,7 @@ pub fn amplitude(input: &[u16; config::adc::BUF_LEN_RAW]) -> [ScalingFactor<u16>
         adjusted_closeness_to_half_max_sample,
     );
 
-    // Step 5: distribute scale factor
-
-    overall_factor.distribute()
+    overall_factor
 }
 
 /// Compute scaling factors for "above threshold" indicator, based on FFT peaks.
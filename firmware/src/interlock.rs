@@ -0,0 +1,99 @@
+use crate::config;
+use crate::time::{Instant, PulseDuration};
+use stm32f1xx_hal::pac::TIM1;
+
+/// State of the interlock, and whether outputs are armed.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Interlock is open, so pulses are inhibited.
+    Open,
+    /// Interlock is closed, but outputs have not been re-armed yet.
+    Closed { since: Instant },
+    /// Interlock is closed and outputs are armed.
+    Armed,
+}
+
+/// Tracks the interlock input, and the sequence required to re-arm outputs after it opens.
+///
+/// While the interlock is open, the pulse timer's break input holds all outputs inactive in hardware.
+/// This additionally prevents the outputs from being automatically re-enabled when the interlock closes,
+/// so output only resumes after the interlock has been closed for `REARM_HOLD`,
+/// and the pulse width control has been turned down to `REARM_MAX_PULSE_WIDTH`.
+pub struct Interlock {
+    state: State,
+}
+
+impl Interlock {
+    pub fn new(closed: bool) -> Self {
+        if closed {
+            Self {
+                state: State::Armed,
+            }
+        } else {
+            defmt::warn!("Interlock is open, pulses inhibited");
+            disable_outputs();
+            Self { state: State::Open }
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether pulses may be fired.
+    pub fn is_armed(&self) -> bool {
+        self.state == State::Armed
+    }
+
+    /// Update the state of the interlock input, when it changes.
+    pub fn set_closed(&mut self, now: Instant, closed: bool) {
+        match (self.state, closed) {
+            (State::Open, false) => {}
+            (_, false) => {
+                defmt::warn!("Interlock opened, pulses inhibited");
+                disable_outputs();
+                self.state = State::Open;
+            }
+            (State::Open, true) => {
+                defmt::info!("Interlock closed, waiting for re-arm");
+                self.state = State::Closed { since: now };
+            }
+            // spurious edge (e.g. from contact bounce)
+            (State::Closed { .. } | State::Armed, true) => {}
+        }
+    }
+
+    /// Re-arm outputs, if the interlock has been closed for long enough and the pulse width control is turned down.
+    ///
+    /// Returns whether outputs were re-armed.
+    pub fn try_rearm(&mut self, now: Instant, pulse_width: PulseDuration) -> bool {
+        if let State::Closed { since } = self.state {
+            let held = match now.checked_duration_since(since) {
+                Some(held) => held >= config::safety::REARM_HOLD,
+                None => false,
+            };
+            if held && pulse_width <= config::safety::REARM_MAX_PULSE_WIDTH {
+                defmt::info!("Interlock re-armed, pulses resumed");
+                enable_outputs();
+                self.state = State::Armed;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Prevent outputs from being re-enabled automatically, once the break input is released.
+fn disable_outputs() {
+    // Safety: the pulse timer driver never modifies BDTR after it has been configured
+    let tim1 = unsafe { &*TIM1::ptr() };
+    tim1.bdtr
+        .modify(|_, w| w.moe().clear_bit().aoe().clear_bit());
+}
+
+/// Allow outputs to be enabled again, which happens on the next update event (i.e. the next pulse).
+fn enable_outputs() {
+    // Safety: the pulse timer driver never modifies BDTR after it has been configured
+    let tim1 = unsafe { &*TIM1::ptr() };
+    tim1.bdtr.modify(|_, w| w.aoe().set_bit());
+}
@@ -1,5 +1,6 @@
-#![no_main]
-#![no_std]
+// built with std on the host for tests, which only cover hardware-independent modules
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 #![allow(
     clippy::assertions_on_constants,
     clippy::let_and_return,
@@ -9,6 +10,7 @@
     clippy::redundant_pattern_matching,
     clippy::type_complexity
 )]
+#![cfg_attr(test, allow(dead_code))]
 #![warn(
     clippy::cast_lossless,
     clippy::cast_possible_truncation,
@@ -17,68 +19,157 @@
     clippy::ptr_as_ptr
 )]
 
+#[cfg(not(test))]
 use defmt_rtt as _; // global logger
+#[cfg(not(test))]
 use stm32f1xx_hal as _; // memory layout
 
-use panic_probe as _; // panicking-behavior
+// same panicking *behavior* as `panic-probe`, but also records the panic in the crash record
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    failsafe::shutdown_outputs();
+    crash::record_panic(Some(info));
+    defmt::error!("{}", defmt::Display2Format(info));
+    cortex_m::asm::udf()
+}
 
-// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// doesn't print a panic message, since `defmt::panic` already has
 // this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[cfg(not(test))]
 #[defmt::panic_handler]
-fn panic() -> ! {
+fn defmt_panic() -> ! {
+    cortex_m::interrupt::disable();
+    failsafe::shutdown_outputs();
+    crash::record_panic(None);
     cortex_m::asm::udf()
 }
 
+// all faults end up here, since the configurable fault handlers (MemManage, BusFault, UsageFault) are left disabled,
+// so they escalate to HardFault, which still records their cause in CFSR
+// panics also end up here, via `udf`, but they've already been recorded
+#[cfg(not(test))]
+#[cortex_m_rt::exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    failsafe::shutdown_outputs();
+    if !crash::is_panic_recorded() {
+        let status = fault::FaultStatus::read();
+        crash::record_hard_fault(frame, &status);
+        fault::log_fault(&status, frame.pc(), frame.lr());
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
 mod adc;
-mod collections;
+mod command;
 mod config;
 mod control;
+mod crash;
+mod failsafe;
+mod fault;
 mod fft;
 mod hal;
 mod indicator;
+mod interlock;
 mod math;
+mod midi;
 mod panic;
 mod pulse;
+mod settings;
+mod standby;
+mod stats;
 mod time;
+mod voice;
+
+// on the host, defmt output from tests is discarded
+#[cfg(test)]
+mod host {
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
 
+    defmt::timestamp!("");
+
+    #[defmt::panic_handler]
+    fn defmt_panic() -> ! {
+        panic!("defmt panic")
+    }
+}
+
+#[cfg(not(test))]
 #[rtic::app(
     device = stm32f1xx_hal::pac,
     peripherals = true,
-    dispatchers = [USART1, USART2, USART3]
+    dispatchers = [USART1, SPI1, SPI2]
 )]
 mod app {
+    use crate::command::{self, Command, Line, LineReader, ParseError, QueueWriter};
     use crate::config;
+    use crate::config::mode::Mode;
     use crate::fft;
-    use crate::fft::analysis::ScratchPeak;
-    use crate::hal::pins;
+    use crate::fft::analysis::{ScratchPeak, Spectrum};
+    use crate::fft::low_band;
     use crate::hal::tim::{OnePulse, OneshotTimer};
+    use crate::hal::{pins, ThresholdControl};
     use crate::indicator;
+    use crate::interlock::{self, Interlock};
     use crate::math::ScaleBy;
+    use crate::midi::encoder::Encoder;
+    use crate::midi::parser::Parser;
+    use crate::midi::tracker::{NoteTracker, Tone};
+    use crate::midi::Message;
     use crate::panic::OptionalExt;
     use crate::pulse;
-    use crate::pulse::{Pulses, UnadjustedPulses};
+    use crate::pulse::interrupter::Interrupter;
+    use crate::pulse::jitter::{self, Jitter};
+    use crate::pulse::limiter::Limiter;
+    use crate::pulse::midi::Synth;
+    use crate::pulse::soft_start::SoftStart;
+    use crate::pulse::thermal::Thermal;
+    use crate::pulse::{Burst, Collisions, Pulses, Timeline, UnadjustedPulses};
+    use crate::settings::Settings;
+    use crate::standby::Standby;
     use crate::time::{Duration, Instant, PulseDuration};
-    use crate::{adc, control};
+    use crate::voice::arpeggiator::Arpeggiator;
+    use crate::voice::{self, Voices};
+    use crate::{adc, control, crash, failsafe, stats};
     use core::sync::atomic::{AtomicU32, Ordering};
     use cortex_m::singleton;
     use dwt_systick_monotonic::DwtSystick;
+    use fugit::Hertz;
+    use heapless::spsc::{Consumer, Producer, Queue};
     use heapless::Vec;
+    use num_complex::Complex;
     use stm32f1xx_hal::adc::{Adc, AdcDma, Continuous};
     use stm32f1xx_hal::device::{ADC1, TIM1, TIM3, TIM4};
     use stm32f1xx_hal::dma::{dma1, CircBuffer, Event};
-    use stm32f1xx_hal::gpio::PinState;
-    use stm32f1xx_hal::pac::ADC2;
+    use stm32f1xx_hal::gpio::{Edge, ExtiPin, PinState};
+    use stm32f1xx_hal::pac::{Interrupt, ADC2, USART2, USART3};
     use stm32f1xx_hal::prelude::*;
+    use stm32f1xx_hal::serial::{self, Rx, Serial, Tx};
     use stm32f1xx_hal::timer::{
         Ch, Channel::*, PwmHz, Tim1NoRemap, Tim3NoRemap, Tim4NoRemap, Timer,
     };
+    use stm32f1xx_hal::watchdog::IndependentWatchdog;
 
     static PULSE_WIDTH_TICKS: AtomicU32 = AtomicU32::new(0);
+    static MIN_PULSE_INTERVAL_TICKS: AtomicU32 = AtomicU32::new(0);
 
     #[shared]
     struct Shared {
         pulses: &'static mut Pulses,
         scheduled_pulse: Option<fire_pulse::SpawnHandle>,
+        thermal: Thermal,
+        jitter: Jitter,
+        interlock: Interlock,
+        settings: Settings,
     }
 
     #[local]
@@ -89,9 +180,35 @@ mod app {
         >,
         fft_buf: &'static mut [i16; config::fft::BUF_LEN_REAL],
         fft_scratch: &'static mut Vec<ScratchPeak, { config::fft::analysis::MAX_SCRATCH_PEAKS }>,
+        low_band_history: &'static mut low_band::History,
+        low_band_bins: &'static mut [Complex<i16>; config::fft::low_band::BUF_LEN],
+        voices: &'static mut Voices,
+        arpeggiator: Arpeggiator,
         next_pulses: &'static mut UnadjustedPulses,
+        next_timeline: &'static mut Timeline,
+        collisions: Collisions,
+        buffer_count: usize,
+        mode: Mode,
+        interrupter: Interrupter,
+        soft_start: SoftStart,
+        standby: Standby,
+        synth: Synth,
+        midi_consumer: Consumer<'static, Message, { config::midi::RX_QUEUE_LEN }>,
+        midi_producer: Producer<'static, Message, { config::midi::RX_QUEUE_LEN }>,
+        midi_parser: Parser,
+        midi_rx: Option<Rx<USART3>>,
+        note_tracker: NoteTracker,
+        midi_encoder: Encoder,
+        midi_out_producer: Producer<'static, u8, { config::midi::TX_QUEUE_LEN }>,
+        midi_out_consumer: Consumer<'static, u8, { config::midi::TX_QUEUE_LEN }>,
+        midi_tx: Tx<USART3>,
+        command_rx: Option<Rx<USART2>>,
+        command_tx: Option<Tx<USART2>>,
+        line_reader: LineReader,
+        command_out_producer: Producer<'static, u8, { config::command::TX_QUEUE_LEN }>,
+        command_out_consumer: Consumer<'static, u8, { config::command::TX_QUEUE_LEN }>,
         adc2_controls: Adc<ADC2>,
-        threshold_control_pin: pins::A2_ADC2C2,
+        threshold_control_pin: ThresholdControl,
         pulse_width_control_pin: pins::A1_ADC2C1,
         amplitude_timer: PwmHz<
             TIM3,
@@ -115,13 +232,31 @@ mod app {
                 pins::B9_TIM4C4,
             ),
         >,
-        pulse_timer:
-            OnePulse<TIM1, Tim1NoRemap, Ch<0>, pins::A8_TIM1C1_PULSE, { config::clk::TIM1CLK_HZ }>,
+        pulse_timer: OnePulse<
+            TIM1,
+            Tim1NoRemap,
+            (Ch<0>, Ch<1>, Ch<2>, Ch<3>),
+            (
+                pins::A8_TIM1C1_PULSE,
+                pins::A9_TIM1C2_PULSE,
+                pins::A10_TIM1C3_PULSE,
+                pins::A11_TIM1C4_PULSE,
+            ),
+            { config::clk::TIM1CLK_HZ },
+        >,
+        pulse_limiter: Limiter,
+        watchdog: IndependentWatchdog,
+        interlock_pin: pins::B12_TIM1_BKIN_INTERLOCK,
         debug_led: pins::C13_DEBUG_LED,
     }
 
     #[init]
     fn init(mut cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        // make sure pulse outputs don't float until the pulse timer is configured
+        failsafe::shutdown_outputs();
+
+        crash::log_and_clear(crash::take_reset_cause());
+
         defmt::info!("Dumping config and log preludes...");
 
         config::dump_to_log();
@@ -129,6 +264,7 @@ mod app {
         adc::log_last_few_samples_prelude();
         fft::log_amplitudes_prelude();
         fft::analysis::log_scratch_peaks_prelude();
+        jitter::log_jitter_prelude();
 
         defmt::info!("Starting init...");
 
@@ -172,11 +308,40 @@ mod app {
 
         defmt::info!("Configuring ADC2 to read control values...");
 
-        let adc2_controls = Adc::adc2(cx.device.ADC2, clocks);
+        let mut adc2_controls = Adc::adc2(cx.device.ADC2, clocks);
 
-        let threshold_control_pin: pins::A2_ADC2C2 = gpioa.pa2.into_analog(&mut gpioa.crl);
+        // the command port uses A2, so the threshold potentiometer must be moved to A4 to enable it
+        let (mut threshold_control_pin, command_pins) = if config::command::ENABLED {
+            let threshold_control_pin: pins::A4_ADC2C4 = gpioa.pa4.into_analog(&mut gpioa.crl);
+            let command_tx: pins::A2_USART2_TX_COMMAND =
+                gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl);
+            let command_rx: pins::A3_USART2_RX_COMMAND =
+                gpioa.pa3.into_floating_input(&mut gpioa.crl);
+            (
+                ThresholdControl::A4(threshold_control_pin),
+                Some((command_tx, command_rx)),
+            )
+        } else {
+            let threshold_control_pin: pins::A2_ADC2C2 = gpioa.pa2.into_analog(&mut gpioa.crl);
+            (ThresholdControl::A2(threshold_control_pin), None)
+        };
         let pulse_width_control_pin: pins::A1_ADC2C1 = gpioa.pa1.into_analog(&mut gpioa.crl);
 
+        defmt::info!("Selecting operating mode...");
+
+        let mode = match config::mode::MODE {
+            Some(mode) => mode,
+            None => {
+                let sample = threshold_control_pin.read(&mut adc2_controls);
+                #[allow(clippy::cast_possible_truncation)]
+                let modes = Mode::ALL.len() as u16;
+                let i = control::Sample::new(sample).to_value_in_range(0..modes);
+                Mode::ALL[usize::from(i).min(Mode::ALL.len() - 1)]
+            }
+        };
+
+        defmt::info!("Operating mode: {}", mode);
+
         defmt::info!("Configuring amplitude indicator timer...");
 
         let tim3_ch4: pins::B1_TIM3C4 = gpiob.pb1.into_alternate_push_pull(&mut gpiob.crl);
@@ -211,12 +376,70 @@ mod app {
             threshold_timer.enable(ch);
         }
 
+        defmt::info!("Configuring interlock input...");
+
+        // this must happen before configuring the pulse timer, since it's also the break input
+        let mut interlock_pin: pins::B12_TIM1_BKIN_INTERLOCK =
+            gpiob.pb12.into_pull_up_input(&mut gpiob.crh);
+        interlock_pin.make_interrupt_source(&mut afio);
+        let mut exti = cx.device.EXTI;
+        interlock_pin.trigger_on_edge(&mut exti, Edge::RisingFalling);
+        interlock_pin.enable_interrupt(&mut exti);
+
+        defmt::info!("Configuring MIDI serial port...");
+
+        let midi_tx: pins::B10_USART3_TX_MIDI_OUT =
+            gpiob.pb10.into_alternate_push_pull(&mut gpiob.crh);
+        let midi_rx: pins::B11_USART3_RX_MIDI_IN = gpiob.pb11.into_floating_input(&mut gpiob.crh);
+
+        let midi_serial = Serial::usart3(
+            cx.device.USART3,
+            (midi_tx, midi_rx),
+            &mut afio.mapr,
+            serial::Config::default().baudrate(config::midi::BAUD_RATE.bps()),
+            clocks,
+        );
+        let (midi_tx, mut midi_rx) = midi_serial.split();
+        // only receive messages in the MIDI mode, so a stray input doesn't take time from other modes
+        let midi_rx = (mode == Mode::Midi).then(|| {
+            midi_rx.listen();
+            midi_rx
+        });
+
+        defmt::info!("Configuring command port...");
+
+        let (command_tx, command_rx) = match command_pins {
+            Some(command_pins) => {
+                defmt::warn!("Command port enabled: threshold control must be wired to A4, not A2");
+                let command_serial = Serial::usart2(
+                    cx.device.USART2,
+                    command_pins,
+                    &mut afio.mapr,
+                    serial::Config::default().baudrate(config::command::BAUD_RATE.bps()),
+                    clocks,
+                );
+                let (command_tx, mut command_rx) = command_serial.split();
+                command_rx.listen();
+                (Some(command_tx), Some(command_rx))
+            }
+            None => {
+                defmt::info!("Command port disabled");
+                (None, None)
+            }
+        };
+
         defmt::info!("Configuring pulse output timer...");
 
         let tim1_ch1: pins::A8_TIM1C1_PULSE = gpioa.pa8.into_alternate_push_pull(&mut gpioa.crh);
+        let tim1_ch2: pins::A9_TIM1C2_PULSE = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+        let tim1_ch3: pins::A10_TIM1C3_PULSE = gpioa.pa10.into_alternate_push_pull(&mut gpioa.crh);
+        let tim1_ch4: pins::A11_TIM1C4_PULSE = gpioa.pa11.into_alternate_push_pull(&mut gpioa.crh);
+
+        let pulse_timer = OneshotTimer::new(cx.device.TIM1, &clocks)
+            .one_pulse_mode((tim1_ch1, tim1_ch2, tim1_ch3, tim1_ch4), &mut afio.mapr);
 
-        let pulse_timer =
-            OneshotTimer::new(cx.device.TIM1, &clocks).one_pulse_mode(tim1_ch1, &mut afio.mapr);
+        // this must happen after configuring the pulse timer, since it may disable outputs
+        let interlock = Interlock::new(interlock_pin.is_low());
 
         defmt::info!("Configuring monotonic timer...");
 
@@ -247,32 +470,95 @@ mod app {
             singleton!(: Vec<ScratchPeak, { config::fft::analysis::MAX_SCRATCH_PEAKS }> = Vec::new())
                 .unwrap();
 
-        let pulses = singleton!(: Pulses = Pulses::new()).unwrap();
+        let low_band_history = singleton!(: low_band::History = low_band::History::new()).unwrap();
+
+        let low_band_bins =
+            singleton!(: [Complex<i16>; config::fft::low_band::BUF_LEN] = [Complex::new(0, 0); config::fft::low_band::BUF_LEN])
+                .unwrap();
+
+        let timeline = singleton!(: Timeline = Timeline::new()).unwrap();
+        let pulses = Pulses::new(timeline);
+        let pulses = singleton!(: Pulses = pulses).unwrap();
+
+        let voices = singleton!(: Voices = Voices::new()).unwrap();
 
         let next_pulses = singleton!(: UnadjustedPulses = UnadjustedPulses::new()).unwrap();
 
+        let next_timeline = singleton!(: Timeline = Timeline::new()).unwrap();
+
+        let midi_queue =
+            singleton!(: Queue<Message, { config::midi::RX_QUEUE_LEN }> = Queue::new()).unwrap();
+        let (midi_producer, midi_consumer) = midi_queue.split();
+
+        let midi_out_queue =
+            singleton!(: Queue<u8, { config::midi::TX_QUEUE_LEN }> = Queue::new()).unwrap();
+        let (midi_out_producer, midi_out_consumer) = midi_out_queue.split();
+
+        let command_out_queue =
+            singleton!(: Queue<u8, { config::command::TX_QUEUE_LEN }> = Queue::new()).unwrap();
+        let (command_out_producer, command_out_consumer) = command_out_queue.split();
+
         defmt::info!("Starting ADC DMA transfer...");
 
         let adc1_dma_transfer = adc1_dma.circ_read(adc_dma_buf);
 
+        defmt::info!("Starting watchdog...");
+
+        let mut watchdog = IndependentWatchdog::new(cx.device.IWDG);
+        watchdog.stop_on_debug(&cx.device.DBGMCU, true);
+        watchdog.start(config::safety::WATCHDOG_TIMEOUT);
+
         defmt::info!("Finished init.");
 
         (
             Shared {
                 pulses,
                 scheduled_pulse: None,
+                thermal: Thermal::new(),
+                jitter: Jitter::new(),
+                interlock,
+                settings: Settings::new(),
             },
             Local {
                 adc1_dma_transfer,
                 fft_buf,
                 fft_scratch,
+                low_band_history,
+                low_band_bins,
+                voices,
+                arpeggiator: Arpeggiator::new(),
                 next_pulses,
+                next_timeline,
+                collisions: Collisions::new(),
+                buffer_count: 0,
+                mode,
+                interrupter: Interrupter::new(),
+                soft_start: SoftStart::new(),
+                standby: Standby::new(),
+                synth: Synth::new(),
+                midi_consumer,
+                midi_producer,
+                midi_parser: Parser::new(),
+                midi_rx,
+                note_tracker: NoteTracker::new(),
+                midi_encoder: Encoder::new(),
+                midi_out_producer,
+                midi_out_consumer,
+                midi_tx,
+                command_rx,
+                command_tx,
+                line_reader: LineReader::new(),
+                command_out_producer,
+                command_out_consumer,
                 adc2_controls,
                 threshold_control_pin,
                 pulse_width_control_pin,
                 amplitude_timer,
                 threshold_timer,
                 pulse_timer,
+                pulse_limiter: Limiter::new(),
+                watchdog,
+                interlock_pin,
                 debug_led: led,
             },
             init::Monotonics(mono),
@@ -281,11 +567,15 @@ mod app {
 
     // Task priorities
     //
-    // Prio | Task         | Description
-    //   16 | fire_pulse   | outputs pulses (triggered by timer interrupt)
-    //   15 | DwtMono      | monotonic timer interrupt
-    //   14 | swap_buffers | schedules pulse timing and processes ADC buffers
-    //    0 | idle         | idle task
+    // Prio | Task              | Description
+    //   16 | fire_pulse        | outputs pulses (triggered by timer interrupt)
+    //   16 | interlock_changed | stops pulses when the interlock opens (triggered by EXTI interrupt)
+    //   15 | DwtMono           | monotonic timer interrupt
+    //   15 | midi_serial       | receives and sends MIDI bytes (triggered by USART interrupt)
+    //   15 | command_serial    | receives and sends command port bytes (triggered by USART interrupt)
+    //   14 | swap_buffers      | schedules pulse timing and processes ADC buffers
+    //    1 | handle_command    | runs commands received on the command port
+    //    0 | idle              | idle task
 
     /// This provides a monotonic timer used to trigger scheduled tasks.
     #[monotonic(
@@ -302,44 +592,297 @@ mod app {
         shared = [
             pulses,
             scheduled_pulse,
+            thermal,
+            jitter,
+            interlock,
         ],
         local = [
             pulse_timer,
+            pulse_limiter,
         ],
         priority = 16,
     )]
     fn fire_pulse(cx: fire_pulse::Context, now: Instant) {
-        (cx.shared.pulses, cx.shared.scheduled_pulse).lock(|pulses, scheduled_pulse| {
-            // load pulse width
-            let pulse_width = PulseDuration::from_ticks(PULSE_WIDTH_TICKS.load(Ordering::Relaxed));
-
-            // fire timer
-            cx.local.pulse_timer.fire(pulse_width);
-
-            // log
-            if config::debug::LOG_ALL_PULSES {
-                defmt::println!(
-                    "Firing {}.{} us pulse at {} us",
-                    pulse_width.to_nanos() / 1000,
-                    pulse_width.to_nanos() % 1000,
-                    Duration::from_ticks(now.ticks()).to_micros()
-                );
-            }
-
-            // consume this pulse (rescheduling this frequency)
-            if let Err(()) = pulses.try_consume_pulse(now) {
-                defmt::warn!("Pulse was not present in pulse train");
-                return;
+        let mut shared = (
+            cx.shared.pulses,
+            cx.shared.scheduled_pulse,
+            cx.shared.thermal,
+            cx.shared.jitter,
+            cx.shared.interlock,
+        );
+        shared.lock(|pulses, scheduled_pulse, thermal, jitter, interlock| {
+            // consume this pulse from the timeline
+            // (if it isn't there, there's no width or outputs to fire it with, so it's dropped)
+            let consumed = match pulses.try_consume_pulse(now) {
+                Ok(consumed) => consumed,
+                Err(()) => {
+                    stats::PULSES_MISSING.increment();
+                    defmt::warn!("Pulse was not present in pulse train");
+                    return;
+                }
+            };
+
+            // load pulse width and soft-start interval
+            let max_pulse_width =
+                PulseDuration::from_ticks(PULSE_WIDTH_TICKS.load(Ordering::Relaxed));
+            let min_interval =
+                Duration::from_ticks(MIN_PULSE_INTERVAL_TICKS.load(Ordering::Relaxed));
+
+            // scale by the pulse's own width (and widen for merged pulses), and route to its outputs
+            let pulse_width = consumed.width(max_pulse_width);
+            let channels = consumed.channels();
+            let repeat = consumed.repeat();
+
+            // derate based on thermal load
+            let pulse_width = thermal.derate(pulse_width);
+
+            // limit duty cycle and off-time (skipping pulses between bursts, or while the interlock is open)
+            let pulse_width = if !interlock.is_armed() || pulses.is_burst_off(now) {
+                None
+            } else {
+                cx.local
+                    .pulse_limiter
+                    .limit(now, pulse_width, repeat, min_interval)
+            };
+
+            match pulse_width {
+                Some(pulse_width) => {
+                    // fire timer
+                    let fired = monotonics::now();
+                    cx.local.pulse_timer.fire(pulse_width, channels, repeat);
+
+                    // measure jitter
+                    jitter.record(now, fired);
+                    stats::PULSES_FIRED.increment();
+
+                    // accumulate heat (from all sub-pulses)
+                    thermal.heat(pulse_width * u32::from(repeat.count.max(1)));
+
+                    // log
+                    if config::debug::LOG_ALL_PULSES {
+                        defmt::println!(
+                            "Firing {}.{} us pulse (x{}) at {} us",
+                            pulse_width.to_nanos() / 1000,
+                            pulse_width.to_nanos() % 1000,
+                            repeat.count,
+                            Duration::from_ticks(now.ticks()).to_micros()
+                        );
+                    }
+                }
+                None => {
+                    stats::PULSES_DROPPED.increment();
+
+                    // log
+                    if config::debug::LOG_ALL_PULSES {
+                        defmt::println!(
+                            "Dropping pulse at {} us due to interlock, burst, duty cycle, or thermal limit",
+                            Duration::from_ticks(now.ticks()).to_micros()
+                        );
+                    }
+                }
             }
 
             // reschedule ourselves for the next pulse
-            if let Some(next_pulse) = pulses.next_pulse(now) {
+            if let Some(next_pulse) = pulses.next_pulse() {
                 match fire_pulse::spawn_at(next_pulse, next_pulse) {
                     Ok(handle) => *scheduled_pulse = Some(handle),
-                    Err(_) => defmt::warn!("Internal fire_pulse schedule overrun"),
+                    Err(_) => {
+                        stats::SCHEDULE_OVERRUNS.increment();
+                        defmt::warn!("Internal fire_pulse schedule overrun");
+                    }
+                }
+            }
+        });
+    }
+
+    /// This task tracks the state of the interlock input.
+    ///
+    /// Outputs are already inhibited in hardware by the break input when the interlock opens,
+    /// so this only needs to stop pulses from being scheduled.
+    #[task(
+        binds = EXTI15_10,
+        shared = [
+            pulses,
+            scheduled_pulse,
+            interlock,
+        ],
+        local = [
+            interlock_pin,
+        ],
+        priority = 16,
+    )]
+    fn interlock_changed(cx: interlock_changed::Context) {
+        cx.local.interlock_pin.clear_interrupt_pending_bit();
+
+        let now = monotonics::now();
+        let closed = cx.local.interlock_pin.is_low();
+
+        let mut shared = (
+            cx.shared.pulses,
+            cx.shared.scheduled_pulse,
+            cx.shared.interlock,
+        );
+        shared.lock(|pulses, scheduled_pulse, interlock| {
+            // Step 1: update interlock state
+            interlock.set_closed(now, closed);
+
+            if !interlock.is_armed() {
+                // Step 2: cancel scheduled next pulse
+                if let Some(handle) = scheduled_pulse.take() {
+                    if let Err(e) = handle.cancel() {
+                        stats::CANCEL_FAILURES.increment();
+                        defmt::warn!("In-flight pulse could not be cancelled: {}", e);
+                    }
+                }
+
+                // Step 3: discard remaining pulses
+                pulses.clear();
+            }
+        });
+    }
+
+    /// This task receives MIDI bytes, queueing complete messages for `swap_buffers`,
+    /// and sends MIDI bytes queued by `swap_buffers`.
+    ///
+    /// This runs for every byte, so it must be short, but it doesn't affect pulse timing.
+    #[task(
+        binds = USART3,
+        local = [
+            midi_rx,
+            midi_parser,
+            midi_producer,
+            midi_tx,
+            midi_out_consumer,
+        ],
+        priority = 15,
+    )]
+    fn midi_serial(cx: midi_serial::Context) {
+        // Step 1: receive a byte, if one is ready (only in the MIDI mode)
+        let received = match cx.local.midi_rx.as_mut().map(|rx| rx.read()) {
+            Some(Ok(byte)) => Some(byte),
+            Some(Err(nb::Error::Other(e))) => {
+                defmt::warn!("MIDI receive error: {}", defmt::Debug2Format(&e));
+                None
+            }
+            Some(Err(nb::Error::WouldBlock)) | None => None,
+        };
+
+        // Step 2: parse and queue messages
+        if let Some(message) = received.and_then(|byte| cx.local.midi_parser.push(byte)) {
+            if config::debug::LOG_MIDI_MESSAGES {
+                defmt::println!("MIDI: {}", message);
+            }
+            if let Err(_) = cx.local.midi_producer.enqueue(message) {
+                defmt::warn!("MIDI receive queue full, dropping message");
+            }
+        }
+
+        // Step 3: send the next queued byte, and keep listening until the queue is empty
+        // (`swap_buffers` triggers this task after queueing bytes, to start sending again)
+        match cx.local.midi_out_consumer.peek() {
+            Some(&byte) => {
+                if cx.local.midi_tx.write(byte).is_ok() {
+                    cx.local.midi_out_consumer.dequeue();
+                }
+                cx.local.midi_tx.listen();
+            }
+            None => cx.local.midi_tx.unlisten(),
+        }
+    }
+
+    /// This task receives command port bytes, spawning `handle_command` for each complete line,
+    /// and sends response bytes queued by `handle_command`.
+    ///
+    /// This runs for every byte, so it must be short, but it doesn't affect pulse timing.
+    #[task(
+        binds = USART2,
+        local = [
+            command_rx,
+            command_tx,
+            line_reader,
+            command_out_consumer,
+        ],
+        priority = 15,
+    )]
+    fn command_serial(cx: command_serial::Context) {
+        let (command_rx, command_tx) = match (cx.local.command_rx, cx.local.command_tx) {
+            (Some(command_rx), Some(command_tx)) => (command_rx, command_tx),
+            // the command port is disabled
+            _ => return,
+        };
+
+        // Step 1: receive a byte, if one is ready
+        let received = match command_rx.read() {
+            Ok(byte) => Some(byte),
+            Err(nb::Error::Other(e)) => {
+                defmt::warn!("Command port receive error: {}", defmt::Debug2Format(&e));
+                None
+            }
+            Err(nb::Error::WouldBlock) => None,
+        };
+
+        // Step 2: assemble and run lines
+        if let Some(line) = received.and_then(|byte| cx.local.line_reader.push(byte)) {
+            if let Err(_) = handle_command::spawn(line) {
+                defmt::warn!("Command port busy, dropping command");
+            }
+        }
+
+        // Step 3: send the next queued byte, and keep listening until the queue is empty
+        // (`handle_command` triggers this task after queueing bytes, to start sending again)
+        match cx.local.command_out_consumer.peek() {
+            Some(&byte) => {
+                if command_tx.write(byte).is_ok() {
+                    cx.local.command_out_consumer.dequeue();
                 }
+                command_tx.listen();
             }
+            None => command_tx.unlisten(),
+        }
+    }
+
+    /// This task runs a command received on the command port, and queues its response.
+    ///
+    /// This has the lowest priority, since responses may need to wait for space to be queued,
+    /// and settings only take effect on the next buffer anyways.
+    #[task(
+        shared = [
+            settings,
+        ],
+        local = [
+            command_out_producer,
+        ],
+        priority = 1,
+    )]
+    fn handle_command(mut cx: handle_command::Context, line: Result<Line, ParseError>) {
+        let mut out = QueueWriter::new(cx.local.command_out_producer, || {
+            rtic::pend(Interrupt::USART2)
         });
+
+        // Step 1: parse the command
+        let command = match line.and_then(|line| command::parse(&line)) {
+            Ok(Some(command)) => command,
+            Ok(None) => return,
+            Err(e) => {
+                if let Err(_) = command::write_error(e, &mut out) {
+                    defmt::warn!("Command port response failed");
+                }
+                return;
+            }
+        };
+
+        // Step 2: run it against a copy of the settings,
+        // so the lock isn't held while writing the response (which would delay `swap_buffers`)
+        let mut settings = cx.shared.settings.lock(|settings| settings.clone());
+        if let Err(_) = command::execute(command, &mut settings, &mut out) {
+            defmt::warn!("Command port response failed");
+        }
+
+        // Step 3: store changed settings, for the next buffer
+        if let Command::Set(..) | Command::Reset = command {
+            defmt::info!("Command port: {}", defmt::Debug2Format(&command));
+            cx.shared.settings.lock(|shared| *shared = settings);
+        }
     }
 
     /// This task schedules pulse timings, from the previous buffer,
@@ -357,22 +900,43 @@ mod app {
         shared = [
             pulses,
             scheduled_pulse,
+            thermal,
+            jitter,
+            interlock,
+            settings,
         ],
         local = [
             adc1_dma_transfer,
             fft_buf,
             fft_scratch,
+            low_band_history,
+            low_band_bins,
+            voices,
+            arpeggiator,
             next_pulses,
+            next_timeline,
+            collisions,
+            buffer_count,
+            mode,
+            interrupter,
+            soft_start,
+            standby,
+            synth,
+            midi_consumer,
+            note_tracker,
+            midi_encoder,
+            midi_out_producer,
             adc2_controls,
             threshold_control_pin,
             pulse_width_control_pin,
             amplitude_timer,
             threshold_timer,
+            watchdog,
             debug_led,
         ],
         priority = 14,
     )]
-    fn swap_buffers(cx: swap_buffers::Context) {
+    fn swap_buffers(mut cx: swap_buffers::Context) {
         cx.local.debug_led.set_low();
 
         // getting the timestamp must happen a consistent delay after the start of the task,
@@ -384,10 +948,14 @@ mod app {
         // Note that we still need to start scheduling pulses quickly, however,
         // because delays could result in pulses being scheduled in the past.
 
+        // take a copy of the settings, so they're consistent while processing this buffer
+        let settings = cx.shared.settings.lock(|settings| settings.clone());
+
         let mut log_timing = {
             let mut last = start;
+            let enabled = settings.log_timing;
             move |label| {
-                if config::debug::LOG_TIMING {
+                if enabled {
                     let now = monotonics::now();
                     defmt::println!("{} after {}us", label, (now - last).to_micros());
                     last = now;
@@ -395,66 +963,143 @@ mod app {
             }
         };
 
-        // Phase 1: swap in new pulse train and reschedule
+        // Phase 1: swap in new pulse timeline and reschedule
 
-        (cx.shared.pulses, cx.shared.scheduled_pulse).lock(|pulses, scheduled_pulse| {
-            // Step 1: adjust pulses and swap
-            pulses.replace_with_adjusted(cx.local.next_pulses, start);
+        let mut shared = (
+            cx.shared.pulses,
+            cx.shared.scheduled_pulse,
+            &mut cx.shared.interlock,
+        );
+        let stalled = shared.lock(|pulses, scheduled_pulse, interlock| {
+            // Step 1: check that pulses from the previous timeline were fired
+            let stalled = pulses.is_stalled(start);
+
+            // Step 2: swap timelines
+            pulses.swap_timeline(cx.local.next_timeline, start);
+
+            // Step 3: discard the new timeline if outputs aren't armed, or in standby
+            if !interlock.is_armed() || cx.local.standby.is_standby() {
+                pulses.clear();
+            }
 
-            // Step 2: compute next pulse
-            let next_pulse = pulses.next_pulse(start);
+            // Step 4: get next pulse
+            let next_pulse = pulses.next_pulse();
 
-            // Step 3: cancel existing scheduled next pulse
+            // Step 5: cancel existing scheduled next pulse
             if let Some(handle) = scheduled_pulse.take() {
                 if let Err(e) = handle.cancel() {
+                    stats::CANCEL_FAILURES.increment();
                     defmt::warn!("In-flight pulse could not be cancelled: {}", e);
                 }
             }
 
-            // Step 4: schedule task for next pulse
+            // Step 6: schedule task for next pulse
             if let Some(next_pulse) = next_pulse {
                 match fire_pulse::spawn_at(next_pulse, next_pulse) {
                     Ok(handle) => *scheduled_pulse = Some(handle),
-                    Err(_) => defmt::warn!("External fire_pulse schedule overrun"),
+                    Err(_) => {
+                        stats::SCHEDULE_OVERRUNS.increment();
+                        defmt::warn!("External fire_pulse schedule overrun");
+                    }
                 }
             }
+
+            stalled
         });
 
         log_timing("Finished swapping in new pulses");
 
+        // feed the watchdog, only if the pulse scheduler is making progress
+        // (if this task or `fire_pulse` hangs, we won't get here at all)
+        if stalled {
+            defmt::warn!("Pulse scheduler stalled, not feeding watchdog");
+        } else {
+            cx.local.watchdog.feed();
+        }
+
+        if config::debug::LOG_PULSE_COLLISIONS
+            && *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0
+        {
+            defmt::println!("Pulse collisions: {}", cx.local.collisions);
+        }
+
+        if config::debug::LOG_PULSE_JITTER
+            && *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0
+        {
+            let jitter = cx.shared.jitter.lock(|jitter| jitter.take());
+            jitter::log_jitter(&jitter);
+        }
+
+        if *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0 {
+            crash::save_stats();
+        }
+
+        if settings.log_stats
+            && *cx.local.buffer_count
+                % (config::adc::BUFFERS_PER_SEC * config::debug::LOG_STATS_INTERVAL_SECS)
+                == 0
+        {
+            stats::log_stats();
+        }
+
         // Phase 2: update current values of controls
 
         // Step 1: read from controls
         let amplitude_threshold = {
-            let sample = cx
-                .local
-                .adc2_controls
-                .read(cx.local.threshold_control_pin)
-                .unwrap_infallible();
+            let sample = cx.local.threshold_control_pin.read(cx.local.adc2_controls);
             control::Sample::new(sample)
         };
-        let pulse_width = {
+        let pulse_width_control = {
             let sample = cx
                 .local
                 .adc2_controls
                 .read(cx.local.pulse_width_control_pin)
                 .unwrap_infallible();
-            control::Sample::new(sample).to_value_in_range_via(
-                config::pulse::DURATION_RANGE,
-                |d| d.ticks(),
-                PulseDuration::from_ticks,
-            )
+            control::Sample::new(sample)
         };
+        let pulse_width = pulse_width_control.to_value_in_range_via(
+            settings.pulse_width(),
+            |d| d.ticks(),
+            PulseDuration::from_ticks,
+        );
 
         log_timing("Finished reading from controls");
 
-        // Step 2: store pulse width
-        PULSE_WIDTH_TICKS.store(pulse_width.ticks(), Ordering::Relaxed);
+        // Step 2: re-arm outputs after the interlock closes
+        let (interlock_state, rearmed) = cx.shared.interlock.lock(|interlock| {
+            let rearmed = interlock.try_rearm(start, pulse_width);
+            (interlock.state(), rearmed)
+        });
+
+        // Step 3: ramp up pulse width (and rate) after power-up or re-arming
+        if rearmed {
+            cx.local.soft_start.restart();
+        }
+        cx.local.soft_start.advance();
+        let max_pulse_width = cx.local.soft_start.pulse_width(pulse_width);
+        let min_interval = cx.local.soft_start.min_interval();
+
+        // Step 4: store pulse width and soft-start interval
+        PULSE_WIDTH_TICKS.store(max_pulse_width.ticks(), Ordering::Relaxed);
+        MIN_PULSE_INTERVAL_TICKS.store(min_interval.ticks(), Ordering::Relaxed);
 
         log_timing("Finished storing pulse width");
 
-        // Step 3: log control values
-        if config::debug::LOG_CONTROL_VALUES {
+        // Step 5: cool thermal model
+        let thermal_load = cx.shared.thermal.lock(|thermal| {
+            thermal.cool();
+            thermal.load()
+        });
+        stats::THERMAL_LOAD.set(thermal_load);
+
+        if settings.log_thermal_load && *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0 {
+            defmt::println!("Thermal load: {}", thermal_load);
+        }
+
+        log_timing("Finished cooling thermal model");
+
+        // Step 6: log control values
+        if settings.log_control_values {
             defmt::println!("Amplitude threshold: {}", amplitude_threshold);
             defmt::println!(
                 "Pulse width: {}.{} us",
@@ -463,17 +1108,46 @@ mod app {
             );
         }
 
+        // Step 7: select window function
+        let window = if config::debug::CYCLE_WINDOWS {
+            let seconds = *cx.local.buffer_count / config::adc::BUFFERS_PER_SEC;
+            let window = config::fft::Window::ALL[seconds % config::fft::Window::ALL.len()];
+            if *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0 {
+                defmt::println!("Window: {}", window);
+            }
+            window
+        } else {
+            settings.window
+        };
+        *cx.local.buffer_count = cx.local.buffer_count.wrapping_add(1);
+
         // Phase 3: process current ADC buffer to prepare for the next swap
 
         let res = cx.local.adc1_dma_transfer.peek(|samples, _| {
             let scratch = cx.local.fft_buf;
 
+            if config::fft::MULTI_RESOLUTION {
+                // Step 0.5: run low band FFT
+                // (this must happen before the main FFT, since it also uses the FFT scratch buffer)
+                low_band::run(
+                    samples,
+                    cx.local.low_band_history,
+                    window,
+                    settings.equalization,
+                    scratch,
+                    cx.local.low_band_bins,
+                );
+
+                log_timing("Finished low band FFT");
+            }
+
             let (values, padding) = scratch.split_at_mut(config::adc::BUF_LEN_PROCESSED);
             let values: &mut [_; config::adc::BUF_LEN_PROCESSED] =
                 values.try_into().unwrap_infallible();
 
             // Step 0: compute and display amplitude from raw samples
-            let amplitude_factors = indicator::amplitude(samples);
+            let amplitude = indicator::amplitude(samples);
+            let amplitude_factors: [_; 4] = amplitude.distribute();
             for (factor, ch) in amplitude_factors.into_iter().zip([C4, C3, C2, C1]) {
                 let duty = cx.local.amplitude_timer.get_max_duty().scale_by(factor);
                 cx.local.amplitude_timer.set_duty(ch, duty);
@@ -490,7 +1164,7 @@ mod app {
             log_timing("Finished processing raw samples");
 
             // Step 2: apply window function and scaling to data
-            fft::window::apply_with_scaling(values);
+            fft::window::apply_with_scaling(window, values);
 
             log_timing("Finished applying window function");
 
@@ -499,9 +1173,9 @@ mod app {
 
             log_timing("Finished FFT");
 
-            if config::fft::EQUALIZATION {
+            if settings.equalization {
                 // Step 4: run equalizer
-                fft::equalizer::apply_to(bins);
+                fft::equalizer::apply_to(bins, config::fft::max_amplitude(window));
 
                 log_timing("Finished equalizer");
             }
@@ -510,19 +1184,182 @@ mod app {
 
             // Step 5: find peaks in spectrum
             let mut peaks = Vec::new();
-            fft::analysis::find_peaks(bins, cx.local.fft_scratch, amplitude_threshold, &mut peaks);
+            if config::fft::MULTI_RESOLUTION {
+                let spectra = [
+                    Spectrum::new(
+                        &bins[..],
+                        config::fft::low_band::MAIN_SEARCH_START..bins.len(),
+                        config::fft::FREQ_RESOLUTION_X1000,
+                        0,
+                    ),
+                    Spectrum::new(
+                        &cx.local.low_band_bins[..],
+                        0..config::fft::low_band::SEARCH_LEN,
+                        config::fft::low_band::FREQ_RESOLUTION_X1000,
+                        cx.local.low_band_history.delay(),
+                    ),
+                ];
+                fft::analysis::find_peaks(
+                    &spectra,
+                    cx.local.fft_scratch,
+                    amplitude_threshold,
+                    settings.noise_floor,
+                    settings.max_peaks,
+                    &mut peaks,
+                );
+            } else {
+                let spectra = [Spectrum::new(
+                    &bins[..],
+                    0..bins.len(),
+                    config::fft::FREQ_RESOLUTION_X1000,
+                    0,
+                )];
+                fft::analysis::find_peaks(
+                    &spectra,
+                    cx.local.fft_scratch,
+                    amplitude_threshold,
+                    settings.noise_floor,
+                    settings.max_peaks,
+                    &mut peaks,
+                );
+            }
 
-            fft::analysis::log_peaks(&peaks);
+            if settings.log_fft_peaks {
+                fft::analysis::log_peaks(&peaks);
+            }
 
             log_timing("Finished peak detection");
 
-            // Step 6: compute pulses based on peaks
-            pulse::schedule_pulses(&peaks, cx.local.next_pulses);
+            if *cx.local.mode == Mode::Audio {
+                // Step 6: enter or leave standby, based on whether the input is silent
+                cx.local.standby.update(amplitude, peaks.len());
+            }
+
+            match *cx.local.mode {
+                Mode::Audio => {
+                    // Step 7: track voices across buffers
+                    cx.local.voices.update(&peaks);
+
+                    if settings.log_voices {
+                        voice::log_voices(cx.local.voices);
+                    }
+
+                    if config::pulse::ARPEGGIATOR {
+                        cx.local.arpeggiator.advance(cx.local.voices);
+                    }
+
+                    log_timing("Finished voice tracking");
+
+                    if config::midi::OUTPUT_ENABLED {
+                        // Step 7.5: send voices as MIDI notes
+                        let tones: Vec<Tone, { config::fft::analysis::MAX_PEAKS }> = cx
+                            .local
+                            .voices
+                            .iter()
+                            .filter(|voice| voice.is_held())
+                            .map(|voice| Tone {
+                                id: voice.id(),
+                                freq_hz: voice.peak().freq().to_Hz(),
+                                amplitude: voice.peak().amplitude(),
+                            })
+                            .collect();
+                        let encoder = &mut *cx.local.midi_encoder;
+                        // resend the status every second, for receivers connected mid-stream
+                        if *cx.local.buffer_count % config::adc::BUFFERS_PER_SEC == 0 {
+                            encoder.reset();
+                        }
+                        let producer = &mut *cx.local.midi_out_producer;
+                        cx.local.note_tracker.update(
+                            &tones,
+                            config::fft::max_amplitude(window),
+                            settings.noise_floor,
+                            |message| {
+                                // drop whole messages if the queue is full, so the stream stays valid
+                                if producer.capacity() - producer.len() < 3 {
+                                    defmt::warn!("MIDI send queue full, dropping {}", message);
+                                    encoder.reset();
+                                    return;
+                                }
+                                for byte in encoder.encode(message) {
+                                    if let Err(_) = producer.enqueue(byte) {
+                                        defmt::warn!("MIDI send queue overflowed (impossible)");
+                                    }
+                                }
+                            },
+                        );
+                        rtic::pend(Interrupt::USART3);
+
+                        log_timing("Finished MIDI output");
+                    }
+
+                    // Step 8: compute pulses based on voices
+                    let max_amplitude = config::fft::max_amplitude(window);
+                    if config::pulse::ARPEGGIATOR {
+                        pulse::schedule_pulses(
+                            cx.local.arpeggiator.playing(cx.local.voices),
+                            max_amplitude,
+                            settings.noise_floor,
+                            cx.local.next_pulses,
+                        );
+                    } else {
+                        pulse::schedule_pulses(
+                            cx.local.voices.sounding(),
+                            max_amplitude,
+                            settings.noise_floor,
+                            cx.local.next_pulses,
+                        );
+                    }
+                }
+                mode @ (Mode::FixedRate | Mode::Burst) => {
+                    // Step 7: read pulse rate from threshold control, and burst on-time from pulse width control
+                    let rate = amplitude_threshold.to_value_in_range(config::mode::RATE_RANGE);
+                    let burst = (mode == Mode::Burst).then(|| {
+                        let on = pulse_width_control.to_value_in_range_via(
+                            config::mode::BURST_ON_RANGE,
+                            |d| d.ticks(),
+                            Duration::from_ticks,
+                        );
+                        Burst {
+                            on,
+                            off: config::mode::BURST_PERIOD - on,
+                        }
+                    });
+
+                    // Step 8: compute pulses at a fixed rate
+                    cx.local.interrupter.schedule(
+                        Hertz::<u32>::Hz(u32::from(rate)),
+                        burst,
+                        cx.local.next_pulses,
+                    );
+                }
+                Mode::Midi => {
+                    // Step 7: update notes from received messages
+                    while let Some(message) = cx.local.midi_consumer.dequeue() {
+                        cx.local.synth.handle(message);
+                    }
+
+                    // Step 8: compute pulses based on notes
+                    cx.local.synth.schedule(cx.local.next_pulses);
+                }
+            }
 
             log_timing("Finished pulse scheduling");
 
-            // Step 7: compute and display "above threshold" from peaks
-            let threshold_factors = indicator::threshold(&peaks);
+            // Step 9: merge pulses into the timeline for the next buffer
+            pulse::build_timeline(
+                cx.local.next_pulses,
+                cx.local.collisions,
+                cx.local.next_timeline,
+            );
+
+            log_timing("Finished building pulse timeline");
+
+            // Step 10: compute and display "above threshold" from peaks (or thermal load)
+            let threshold_factors = if config::indicator::SHOW_THERMAL_LOAD {
+                thermal_load.distribute()
+            } else {
+                indicator::threshold(&peaks)
+            };
             for (factor, ch) in threshold_factors.into_iter().zip([C1, C2, C3, C4]) {
                 let duty = cx.local.threshold_timer.get_max_duty().scale_by(factor);
                 cx.local.threshold_timer.set_duty(ch, duty);
@@ -531,15 +1368,39 @@ mod app {
             log_timing("Finished computing indicated above threshold");
         });
 
-        if let Err(_) = res {
-            let duration = monotonics::now() - start;
-            defmt::warn!(
-                "ADC buffer processing did not complete in time (took {} us).",
-                duration.to_micros()
-            );
+        let duration = monotonics::now() - start;
+        stats::WORST_SWAP_BUFFERS.record(duration);
+
+        match res {
+            Ok(()) => stats::PROCESSED_FRAMES.increment(),
+            Err(_) => {
+                stats::LATE_FRAMES.increment();
+                defmt::warn!(
+                    "ADC buffer processing did not complete in time (took {} us).",
+                    duration.to_micros()
+                );
+            }
         }
 
-        cx.local.debug_led.set_high();
+        // show whether outputs are armed on the debug LED
+        // (solid = interlock open, blinking = waiting for re-arm, brief flash every 2 seconds = standby,
+        //  otherwise it's lit while processing)
+        match interlock_state {
+            interlock::State::Armed if cx.local.standby.is_standby() => {
+                if *cx.local.buffer_count % (config::adc::BUFFERS_PER_SEC * 2)
+                    >= config::adc::BUFFERS_PER_SEC / 8
+                {
+                    cx.local.debug_led.set_high();
+                }
+            }
+            interlock::State::Armed => cx.local.debug_led.set_high(),
+            interlock::State::Closed { .. } => {
+                if *cx.local.buffer_count / (config::adc::BUFFERS_PER_SEC / 4) % 2 == 0 {
+                    cx.local.debug_led.set_high();
+                }
+            }
+            interlock::State::Open => {}
+        }
     }
 
     #[idle]
@@ -55,7 +55,7 @@ pub fn phase(x: Complex<i16>) -> ScalingFactor<u16> {
 /// A scaling factor, from zero to one.
 ///
 /// Internally, zero is represented as `0`, and 1 is represented as `T::MAX`.
-#[derive(Copy, Clone, Format)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Format)]
 pub struct ScalingFactor<T>(T);
 
 impl ScalingFactor<u16> {
@@ -66,6 +66,11 @@ impl ScalingFactor<u16> {
         Self(factor)
     }
 
+    /// The raw value, with `MAX` representing one.
+    pub const fn raw(self) -> u16 {
+        self.0
+    }
+
     /// Construct a scaling factor from a sample with limited bits.
     #[track_caller]
     pub const fn from_sample<const BITS: u32>(sample: u16) -> Self {
@@ -85,6 +90,11 @@ impl ScalingFactor<u16> {
         Self(factor)
     }
 
+    /// Add two factors, wrapping around past one (e.g. for phases).
+    pub fn wrapping_add(self, other: Self) -> Self {
+        Self(self.0.wrapping_add(other.0))
+    }
+
     /// Split factor up into N buckets.
     ///
     /// For example, an overall scale factor of 62.5% (5/8) would be distributed over 4 buckets to: 100% 100% 50% 0%.
@@ -93,13 +103,13 @@ impl ScalingFactor<u16> {
 
         for (i, factor) in factors.iter_mut().enumerate() {
             let overall_factor = self.0;
-            let max_factor_over_n: u16 = u16::MAX / N.truncate();
+            let max_factor_over_n: u16 = u16::MAX / Truncate::<u16>::truncate(N);
             let local_factor_over_n: u16 =
-                overall_factor.saturating_sub(i.truncate() * max_factor_over_n);
+                overall_factor.saturating_sub(Truncate::<u16>::truncate(i) * max_factor_over_n);
             let local_factor: u16 = if local_factor_over_n >= max_factor_over_n {
                 u16::MAX
             } else {
-                local_factor_over_n * N.truncate()
+                local_factor_over_n * Truncate::<u16>::truncate(N)
             };
             *factor = Self(local_factor);
         }
@@ -162,6 +172,8 @@ macro_rules! impl_truncate {
 }
 
 impl_truncate!(usize => u16);
+impl_truncate!(usize => u8);
+impl_truncate!(u16 => u8);
 impl_truncate!(u32 => u16);
 impl_truncate!(isize => i16);
 impl_truncate!(i32 => i16);
@@ -0,0 +1,45 @@
+use defmt::Format;
+
+pub mod encoder;
+pub mod parser;
+pub mod tracker;
+
+/// A MIDI channel message.
+///
+/// Channels are numbered from 0, so channel 1 on a keyboard is channel 0 here.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Format)]
+pub enum Message {
+    /// Note-off, which is also sent as note-on with velocity 0
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Pitch bend, from -8192 (lowest) through 0 (centered) to 8191 (highest)
+    PitchBend {
+        channel: u8,
+        bend: i16,
+    },
+}
+
+/// Frequency of each MIDI note in equal temperament, in millihertz.
+pub const NOTE_FREQS_MILLIHZ: [u32; 128] =
+    include!(concat!(env!("OUT_DIR"), "/midi_note_freqs.rs"));
+
+/// Pitches between notes are in units of `1 / BEND_PER_SEMITONE` semitone,
+/// which is the resolution of pitch bend with a range of one semitone.
+pub const BEND_PER_SEMITONE: i32 = 8192;
+
+/// Controller number which releases all notes on a channel (without waiting for note-offs)
+pub const ALL_SOUND_OFF: u8 = 120;
+/// Controller number which releases all notes on a channel
+pub const ALL_NOTES_OFF: u8 = 123;
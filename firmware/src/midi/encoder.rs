@@ -0,0 +1,203 @@
+use crate::midi::Message;
+
+/// Encodes channel messages into a serial MIDI byte stream, using running status.
+///
+/// This has no hardware dependencies, so its output can be checked against the parser.
+pub struct Encoder {
+    /// Status byte of the last message sent, which following messages can omit
+    status: Option<u8>,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Self { status: None }
+    }
+
+    /// Forget the running status, so the next message includes its status byte.
+    ///
+    /// This must be called if encoded bytes are discarded,
+    /// and can be called periodically so a receiver connected mid-stream picks up the status.
+    pub fn reset(&mut self) {
+        self.status = None;
+    }
+
+    /// Encode a message, returning its bytes (at most 3).
+    pub fn encode(&mut self, message: Message) -> impl Iterator<Item = u8> {
+        let (kind, channel, data) = match message {
+            // sent as note-on with velocity 0, so runs of note-ons and note-offs share running status
+            Message::NoteOff { channel, note } => (0x90, channel, [note, 0]),
+            Message::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (0x90, channel, [note, velocity]),
+            Message::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (0xB0, channel, [controller, value]),
+            Message::PitchBend { channel, bend } => {
+                // 14 bits, least significant 7 bits first
+                let value = bend.clamp(-0x2000, 0x1FFF) + 0x2000;
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let (lsb, msb) = ((value & 0x7F) as u8, (value >> 7) as u8);
+                (0xE0, channel, [lsb, msb])
+            }
+        };
+        let status = kind | (channel & 0x0F);
+        let data = data.map(|byte| byte & 0x7F);
+
+        let status_byte = (self.status != Some(status)).then_some(status);
+        self.status = Some(status);
+        status_byte.into_iter().chain(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::parser::Parser;
+
+    fn encode_all(encoder: &mut Encoder, messages: &[Message]) -> Vec<u8> {
+        messages
+            .iter()
+            .flat_map(|&message| encoder.encode(message).collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[test]
+    fn running_status() {
+        let mut encoder = Encoder::new();
+        let bytes = encode_all(
+            &mut encoder,
+            &[
+                Message::NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100,
+                },
+                Message::NoteOn {
+                    channel: 0,
+                    note: 64,
+                    velocity: 90,
+                },
+                // sent as note-on with velocity 0, so it shares the running status
+                Message::NoteOff {
+                    channel: 0,
+                    note: 60,
+                },
+                // different channel
+                Message::NoteOn {
+                    channel: 1,
+                    note: 67,
+                    velocity: 80,
+                },
+                // different message type
+                Message::PitchBend {
+                    channel: 1,
+                    bend: 0,
+                },
+                Message::NoteOff {
+                    channel: 1,
+                    note: 67,
+                },
+            ],
+        );
+        assert_eq!(
+            bytes,
+            [
+                0x90, 60, 100, //
+                64, 90, //
+                60, 0, //
+                0x91, 67, 80, //
+                0xE1, 0x00, 0x40, //
+                0x91, 67, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_resends_status() {
+        let mut encoder = Encoder::new();
+        let note_on = Message::NoteOn {
+            channel: 2,
+            note: 60,
+            velocity: 100,
+        };
+        assert_eq!(encode_all(&mut encoder, &[note_on]), [0x92, 60, 100]);
+        assert_eq!(encode_all(&mut encoder, &[note_on]), [60, 100]);
+        encoder.reset();
+        assert_eq!(encode_all(&mut encoder, &[note_on]), [0x92, 60, 100]);
+    }
+
+    #[test]
+    fn pitch_bend_range() {
+        let mut encoder = Encoder::new();
+        let mut bend = |bend| {
+            encoder.reset();
+            encode_all(&mut encoder, &[Message::PitchBend { channel: 0, bend }])
+        };
+        assert_eq!(bend(-0x2000), [0xE0, 0x00, 0x00]);
+        assert_eq!(bend(-1), [0xE0, 0x7F, 0x3F]);
+        assert_eq!(bend(0), [0xE0, 0x00, 0x40]);
+        assert_eq!(bend(1), [0xE0, 0x01, 0x40]);
+        assert_eq!(bend(0x1FFF), [0xE0, 0x7F, 0x7F]);
+        // out of range bends are clamped
+        assert_eq!(bend(i16::MIN), [0xE0, 0x00, 0x00]);
+        assert_eq!(bend(i16::MAX), [0xE0, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn data_bytes_never_have_the_status_bit() {
+        let mut encoder = Encoder::new();
+        let bytes = encode_all(
+            &mut encoder,
+            &[Message::NoteOn {
+                channel: 0x1F,
+                note: 0xFF,
+                velocity: 0x80,
+            }],
+        );
+        assert_eq!(bytes, [0x9F, 0x7F, 0x00]);
+    }
+
+    #[test]
+    fn round_trip_through_parser() {
+        let messages = [
+            Message::NoteOn {
+                channel: 3,
+                note: 69,
+                velocity: 127,
+            },
+            Message::PitchBend {
+                channel: 3,
+                bend: -1234,
+            },
+            Message::PitchBend {
+                channel: 3,
+                bend: 4321,
+            },
+            Message::ControlChange {
+                channel: 3,
+                controller: 7,
+                value: 64,
+            },
+            Message::NoteOff {
+                channel: 3,
+                note: 69,
+            },
+            Message::NoteOn {
+                channel: 15,
+                note: 0,
+                velocity: 1,
+            },
+        ];
+        let mut encoder = Encoder::new();
+        let mut parser = Parser::new();
+        let parsed: Vec<Message> = encode_all(&mut encoder, &messages)
+            .into_iter()
+            .filter_map(|byte| parser.push(byte))
+            .collect();
+        assert_eq!(parsed, messages);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::midi::Message;
+
+/// Parses a serial MIDI byte stream into channel messages, including running status.
+///
+/// This has no hardware dependencies, so it can be fed bytes from anywhere.
+pub struct Parser {
+    /// Current status byte, kept after a message for running status
+    status: Option<u8>,
+    /// Data bytes received so far for the current message
+    data: [u8; 2],
+    len: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            status: None,
+            data: [0; 2],
+            len: 0,
+        }
+    }
+
+    /// Feed a single received byte, returning a message if it completes one.
+    pub fn push(&mut self, byte: u8) -> Option<Message> {
+        match byte {
+            // real-time messages (clock, start, stop, active sensing, etc.) may be interleaved anywhere,
+            // and don't affect running status
+            0xF8..=0xFF => None,
+            // system exclusive and system common messages cancel running status
+            // (their data bytes are then ignored, since there is no status)
+            0xF0..=0xF7 => {
+                self.status = None;
+                self.len = 0;
+                None
+            }
+            // channel message status
+            0x80..=0xEF => {
+                self.status = Some(byte);
+                self.len = 0;
+                None
+            }
+            // data byte
+            0x00..=0x7F => {
+                let status = self.status?;
+                self.data[self.len] = byte;
+                self.len += 1;
+                if self.len < data_len(status) {
+                    return None;
+                }
+                // keep the status, so following messages can omit it
+                self.len = 0;
+                decode(status, self.data)
+            }
+        }
+    }
+}
+
+/// Number of data bytes following a channel message status byte.
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        // program change, channel pressure
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+/// Decode a complete channel message, ignoring those which aren't used.
+fn decode(status: u8, data: [u8; 2]) -> Option<Message> {
+    let channel = status & 0x0F;
+    let [first, second] = data;
+    match status & 0xF0 {
+        0x80 => Some(Message::NoteOff {
+            channel,
+            note: first,
+        }),
+        0x90 if second == 0 => Some(Message::NoteOff {
+            channel,
+            note: first,
+        }),
+        0x90 => Some(Message::NoteOn {
+            channel,
+            note: first,
+            velocity: second,
+        }),
+        0xB0 => Some(Message::ControlChange {
+            channel,
+            controller: first,
+            value: second,
+        }),
+        0xE0 => {
+            // 14 bits, least significant 7 bits first
+            let value = (i16::from(second) << 7) | i16::from(first);
+            Some(Message::PitchBend {
+                channel,
+                bend: value - 0x2000,
+            })
+        }
+        // polyphonic key pressure, program change, channel pressure
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<Message> {
+        let mut parser = Parser::new();
+        bytes.iter().filter_map(|&byte| parser.push(byte)).collect()
+    }
+
+    #[test]
+    fn note_on_off() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0x80, 60, 64]),
+            [
+                Message::NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100,
+                },
+                Message::NoteOff {
+                    channel: 0,
+                    note: 60,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn note_on_with_velocity_0_is_note_off() {
+        assert_eq!(
+            parse(&[0x95, 60, 0]),
+            [Message::NoteOff {
+                channel: 5,
+                note: 60,
+            }]
+        );
+    }
+
+    #[test]
+    fn running_status() {
+        assert_eq!(
+            parse(&[0x91, 60, 100, 64, 90, 60, 0, 64, 0]),
+            [
+                Message::NoteOn {
+                    channel: 1,
+                    note: 60,
+                    velocity: 100,
+                },
+                Message::NoteOn {
+                    channel: 1,
+                    note: 64,
+                    velocity: 90,
+                },
+                Message::NoteOff {
+                    channel: 1,
+                    note: 60,
+                },
+                Message::NoteOff {
+                    channel: 1,
+                    note: 64,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn running_status_with_one_data_byte() {
+        // program changes are ignored, but their data bytes must not be mistaken for the start of another message
+        assert_eq!(
+            parse(&[0xC0, 5, 6, 7, 0x90, 60, 100]),
+            [Message::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+            }]
+        );
+    }
+
+    #[test]
+    fn interleaved_realtime_bytes() {
+        // clock, start, continue, stop, active sensing, and reset, in the middle of messages
+        assert_eq!(
+            parse(&[
+                0xF8, 0x92, 0xF8, 60, 0xFA, 100, 0xFB, 64, 0xFC, 0xFE, 90, 0xFF, 0xE2, 0xF8, 0x00,
+                0xF8, 0x40,
+            ]),
+            [
+                Message::NoteOn {
+                    channel: 2,
+                    note: 60,
+                    velocity: 100,
+                },
+                Message::NoteOn {
+                    channel: 2,
+                    note: 64,
+                    velocity: 90,
+                },
+                Message::PitchBend {
+                    channel: 2,
+                    bend: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn system_messages_cancel_running_status() {
+        // sysex, with its data bytes and end
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF0, 0x7E, 60, 100, 0xF7, 64, 90]).len(),
+            1
+        );
+        // song position pointer
+        assert_eq!(parse(&[0x90, 60, 100, 0xF2, 60, 100, 64, 90]).len(), 1);
+    }
+
+    #[test]
+    fn data_bytes_without_status_are_ignored() {
+        assert_eq!(parse(&[60, 100, 64]), []);
+    }
+
+    #[test]
+    fn new_status_interrupts_incomplete_message() {
+        assert_eq!(
+            parse(&[0x90, 60, 0xB3, 7, 127]),
+            [Message::ControlChange {
+                channel: 3,
+                controller: 7,
+                value: 127,
+            }]
+        );
+    }
+
+    #[test]
+    fn pitch_bend() {
+        let bends: Vec<i16> = parse(&[0xE0, 0x00, 0x00, 0x00, 0x40, 0x7F, 0x7F, 0x01, 0x40])
+            .into_iter()
+            .map(|message| match message {
+                Message::PitchBend { channel: 0, bend } => bend,
+                _ => panic!("unexpected message {:?}", message),
+            })
+            .collect();
+        assert_eq!(bends, [-0x2000, 0, 0x1FFF, 1]);
+    }
+}
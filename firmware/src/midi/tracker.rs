@@ -0,0 +1,364 @@
+use crate::config;
+use crate::midi::{Message, BEND_PER_SEMITONE, NOTE_FREQS_MILLIHZ};
+use heapless::Vec;
+
+/// A pitch detected in the audio input, taken from a voice.
+#[derive(Copy, Clone)]
+pub struct Tone {
+    /// Identifies the voice across buffers
+    pub id: u16,
+    pub freq_hz: u32,
+    /// Amplitude of the voice's peak
+    pub amplitude: u16,
+}
+
+/// A note which was sent for a tone, and hasn't been ended yet.
+struct Playing {
+    id: u16,
+    channel: u8,
+    note: u8,
+    /// Last pitch bend sent on this note's channel
+    bend: i16,
+}
+
+/// Converts tones detected in the audio input into MIDI notes, for the audio-to-MIDI output.
+///
+/// This only operates on the tones passed in, so it can be fed synthetic tones.
+pub struct NoteTracker {
+    /// At most one note per tone, and there is at most one tone per peak, so this can't overflow
+    playing: Vec<Playing, { config::fft::analysis::MAX_PEAKS }>,
+}
+
+impl NoteTracker {
+    pub const fn new() -> Self {
+        Self {
+            playing: Vec::new(),
+        }
+    }
+
+    /// Update notes from the tones in the current buffer, emitting messages for notes which start or end.
+    ///
+    /// `max_amplitude` is the amplitude of the loudest possible peak, which is sent with the maximum velocity,
+    /// and `noise_floor` is the amplitude of the quietest possible peak, which is sent with the minimum velocity.
+    #[inline(never)]
+    pub fn update(
+        &mut self,
+        tones: &[Tone],
+        max_amplitude: u16,
+        noise_floor: u16,
+        mut emit: impl FnMut(Message),
+    ) {
+        // Step 1: end notes whose tone has disappeared
+        // (before starting new notes, so their channels can be reused)
+        self.playing.retain(|playing| {
+            let present = tones.iter().any(|tone| tone.id == playing.id);
+            if !present {
+                emit(Message::NoteOff {
+                    channel: playing.channel,
+                    note: playing.note,
+                });
+            }
+            present
+        });
+
+        for tone in tones {
+            let position = position(tone.freq_hz);
+
+            // Step 2: follow the detune of notes which are already playing
+            // (the note itself is fixed at note-on, so it may drift by up to `VOICE_FREQ_TOLERANCE`)
+            if let Some(playing) = self.playing.iter_mut().find(|p| p.id == tone.id) {
+                if config::midi::OUTPUT_PITCH_BEND {
+                    let bend = bend(playing.note, position);
+                    if bend != playing.bend {
+                        playing.bend = bend;
+                        emit(Message::PitchBend {
+                            channel: playing.channel,
+                            bend,
+                        });
+                    }
+                }
+                continue;
+            }
+
+            // Step 3: start notes for new tones
+            let note = nearest_note(position);
+            let channel = match self.free_channel(note) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            let bend = if config::midi::OUTPUT_PITCH_BEND {
+                let bend = bend(note, position);
+                emit(Message::PitchBend { channel, bend });
+                bend
+            } else {
+                0
+            };
+            emit(Message::NoteOn {
+                channel,
+                note,
+                velocity: velocity(tone.amplitude, max_amplitude, noise_floor),
+            });
+            if let Err(_) = self.playing.push(Playing {
+                id: tone.id,
+                channel,
+                note,
+                bend,
+            }) {
+                defmt::warn!("MIDI output notes overflowed (impossible)");
+            }
+        }
+    }
+
+    /// Channel to play a new note on, if possible.
+    ///
+    /// With pitch bend, each note gets its own channel so it can be bent independently.
+    /// Otherwise, all notes share one channel, so a note which is already playing can't be started again.
+    fn free_channel(&self, note: u8) -> Option<u8> {
+        let in_use = |channel: u8| self.playing.iter().any(|p| p.channel == channel);
+        if config::midi::OUTPUT_PITCH_BEND {
+            (config::midi::OUTPUT_CHANNEL..16).find(|&channel| !in_use(channel))
+        } else {
+            let channel = config::midi::OUTPUT_CHANNEL;
+            let playing = self
+                .playing
+                .iter()
+                .any(|p| p.channel == channel && p.note == note);
+            (!playing).then_some(channel)
+        }
+    }
+}
+
+/// Position of a frequency among the notes, in units of `1 / BEND_PER_SEMITONE` semitone from note 0.
+///
+/// This linearly interpolates between adjacent semitones, the inverse of pitch bend in the MIDI mode.
+fn position(freq_hz: u32) -> i32 {
+    // Step 1: find the highest note at or below the frequency (clamped to the range of notes)
+    let freq = freq_hz.saturating_mul(1000);
+    let semitone = NOTE_FREQS_MILLIHZ
+        .partition_point(|&note_freq| note_freq <= freq)
+        .clamp(1, 127)
+        - 1;
+
+    // Step 2: interpolate between it and the next semitone
+    let low = NOTE_FREQS_MILLIHZ[semitone];
+    let high = NOTE_FREQS_MILLIHZ[semitone + 1];
+    #[allow(clippy::cast_sign_loss)]
+    let fraction =
+        u64::from(freq.clamp(low, high) - low) * BEND_PER_SEMITONE as u64 / u64::from(high - low);
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let position = semitone as i32 * BEND_PER_SEMITONE + fraction as i32;
+    position
+}
+
+/// Note closest to a position.
+fn nearest_note(position: i32) -> u8 {
+    let note = (position + BEND_PER_SEMITONE / 2) / BEND_PER_SEMITONE;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let note = note.clamp(0, 127) as u8;
+    note
+}
+
+/// Pitch bend from a note to a position, using `PITCH_BEND_RANGE`.
+fn bend(note: u8, position: i32) -> i16 {
+    let offset = position - i32::from(note) * BEND_PER_SEMITONE;
+    let bend = offset / i32::from(config::midi::PITCH_BEND_RANGE);
+    #[allow(clippy::cast_possible_truncation)]
+    let bend = bend.clamp(-0x2000, 0x1FFF) as i16;
+    bend
+}
+
+/// Map an amplitude to a note-on velocity, from the noise floor to the loudest possible peak.
+fn velocity(amplitude: u16, max_amplitude: u16, floor: u16) -> u8 {
+    let x = if max_amplitude <= floor {
+        127
+    } else {
+        u32::from(amplitude.saturating_sub(floor)) * 127 / u32::from(max_amplitude - floor)
+    };
+    // velocity 0 would be a note-off
+    #[allow(clippy::cast_possible_truncation)]
+    let velocity = x.clamp(1, 127) as u8;
+    velocity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const MAX_AMPLITUDE: u16 = 10_000;
+    const NOISE_FLOOR: u16 = 1_000;
+
+    fn tone(id: u16, freq_hz: u32) -> Tone {
+        Tone {
+            id,
+            freq_hz,
+            amplitude: MAX_AMPLITUDE,
+        }
+    }
+
+    fn update(tracker: &mut NoteTracker, tones: &[Tone]) -> Vec<Message> {
+        let mut messages = Vec::new();
+        tracker.update(tones, MAX_AMPLITUDE, NOISE_FLOOR, |message| {
+            messages.push(message)
+        });
+        messages
+    }
+
+    fn note_ons(messages: &[Message]) -> Vec<(u8, u8)> {
+        messages
+            .iter()
+            .filter_map(|message| match *message {
+                Message::NoteOn { channel, note, .. } => Some((channel, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn note_offs(messages: &[Message]) -> Vec<(u8, u8)> {
+        messages
+            .iter()
+            .filter_map(|message| match *message {
+                Message::NoteOff { channel, note } => Some((channel, note)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn note_on_off_pairing() {
+        let mut tracker = NoteTracker::new();
+
+        // A4 appears
+        let messages = update(&mut tracker, &[tone(1, 440)]);
+        let a4 = note_ons(&messages);
+        assert_eq!(a4.len(), 1);
+        assert_eq!(a4[0].1, 69);
+        assert_eq!(note_offs(&messages), []);
+
+        // A4 is held, and E5 appears
+        let messages = update(&mut tracker, &[tone(1, 440), tone(2, 659)]);
+        let e5 = note_ons(&messages);
+        assert_eq!(e5.len(), 1);
+        assert_eq!(e5[0].1, 76);
+        assert_eq!(note_offs(&messages), []);
+
+        // A4 ends, on the same channel and note it started with
+        let messages = update(&mut tracker, &[tone(2, 659)]);
+        assert_eq!(note_ons(&messages), []);
+        assert_eq!(note_offs(&messages), a4);
+
+        // E5 ends
+        let messages = update(&mut tracker, &[]);
+        assert_eq!(note_ons(&messages), []);
+        assert_eq!(note_offs(&messages), e5);
+
+        // nothing left to end
+        assert_eq!(update(&mut tracker, &[]), []);
+    }
+
+    #[test]
+    fn held_notes_are_not_restarted_when_detuned() {
+        let mut tracker = NoteTracker::new();
+        let messages = update(&mut tracker, &[tone(1, 440)]);
+        assert_eq!(note_ons(&messages).len(), 1);
+
+        // a slight drift keeps the same note
+        let messages = update(&mut tracker, &[tone(1, 445)]);
+        assert_eq!(note_ons(&messages), []);
+        assert_eq!(note_offs(&messages), []);
+    }
+
+    #[test]
+    fn new_voice_replaces_ended_voice() {
+        let mut tracker = NoteTracker::new();
+        let first = note_ons(&update(&mut tracker, &[tone(1, 440)]));
+
+        // a different voice at the same pitch, so the old note must end before the new one starts
+        let messages = update(&mut tracker, &[tone(2, 440)]);
+        assert_eq!(note_offs(&messages), first);
+        assert_eq!(note_ons(&messages).len(), 1);
+        let off = messages
+            .iter()
+            .position(|message| matches!(message, Message::NoteOff { .. }));
+        let on = messages
+            .iter()
+            .position(|message| matches!(message, Message::NoteOn { .. }));
+        assert!(off < on);
+    }
+
+    #[test]
+    fn note_from_frequency() {
+        assert_eq!(nearest_note(position(440)), 69);
+        assert_eq!(nearest_note(position(262)), 60);
+        assert_eq!(nearest_note(position(27)), 21);
+        // a quarter tone sharp of A4 (452.9 Hz) is the boundary between A4 and A#4
+        assert_eq!(nearest_note(position(452)), 69);
+        assert_eq!(nearest_note(position(454)), 70);
+        // out of range frequencies are clamped to the range of notes
+        assert_eq!(nearest_note(position(0)), 0);
+        assert_eq!(nearest_note(position(u32::MAX)), 127);
+    }
+
+    #[test]
+    fn velocity_from_amplitude() {
+        assert_eq!(velocity(MAX_AMPLITUDE, MAX_AMPLITUDE, NOISE_FLOOR), 127);
+        assert_eq!(velocity(u16::MAX, MAX_AMPLITUDE, NOISE_FLOOR), 127);
+        // halfway between the noise floor and the loudest possible peak
+        let half = NOISE_FLOOR + (MAX_AMPLITUDE - NOISE_FLOOR) / 2;
+        assert_eq!(velocity(half, MAX_AMPLITUDE, NOISE_FLOOR), 63);
+        // quiet peaks still start a note, since velocity 0 would end it
+        assert_eq!(velocity(NOISE_FLOOR, MAX_AMPLITUDE, NOISE_FLOOR), 1);
+        assert_eq!(velocity(0, MAX_AMPLITUDE, NOISE_FLOOR), 1);
+        // noise floor above the loudest possible peak
+        assert_eq!(velocity(0, NOISE_FLOOR, MAX_AMPLITUDE), 127);
+    }
+
+    #[test]
+    fn note_on_velocity_follows_peak_amplitude() {
+        let mut tracker = NoteTracker::new();
+        let loud = tone(1, 440);
+        let quiet = Tone {
+            amplitude: NOISE_FLOOR,
+            ..tone(2, 880)
+        };
+        let velocities: Vec<(u8, u8)> = update(&mut tracker, &[loud, quiet])
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::NoteOn { note, velocity, .. } => Some((note, velocity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(velocities, [(69, 127), (81, 1)]);
+    }
+
+    #[test]
+    fn pitch_bend_range() {
+        let range = i32::from(config::midi::PITCH_BEND_RANGE);
+        let note = 60;
+        let at = |semitones_x100: i32| {
+            i32::from(note) * BEND_PER_SEMITONE + semitones_x100 * BEND_PER_SEMITONE / 100
+        };
+
+        assert_eq!(bend(note, at(0)), 0);
+        // the full range of the pitch wheel covers `PITCH_BEND_RANGE` semitones either way
+        assert_eq!(i32::from(bend(note, at(100))), 0x2000 / range);
+        assert_eq!(i32::from(bend(note, at(-100))), -0x2000 / range);
+        assert_eq!(bend(note, at(range * 100)), 0x1FFF);
+        assert_eq!(bend(note, at(-range * 100)), -0x2000);
+        // further than that is clamped
+        assert_eq!(bend(note, at(range * 200)), 0x1FFF);
+        assert_eq!(bend(note, at(-range * 200)), -0x2000);
+    }
+
+    #[test]
+    fn pitch_bend_from_frequency() {
+        // exactly on a note
+        let a4 = position(440);
+        assert_eq!(bend(69, a4), 0);
+        // between A4 (440 Hz) and A#4 (466.16 Hz), sharp of A4 and flat of A#4
+        let between = position(453);
+        assert!(bend(69, between) > 0);
+        assert!(bend(70, between) < 0);
+    }
+}
@@ -1,103 +1,493 @@
-use crate::collections::ReplaceWithMapped;
 use crate::config;
-use crate::fft::analysis::Peak;
-use crate::time::{Duration, Instant};
-use heapless::Vec;
+use crate::config::pulse::{CollisionPolicy, WidthCurve};
+use crate::hal::tim::{Channels, Repeat};
+use crate::math::{amplitude_sqrt, ScaleBy, ScalingFactor, Truncate};
+use crate::time::{Duration, Instant, PulseDuration};
+use crate::voice::Voice;
+use defmt::Format;
+use heapless::{Deque, Vec};
 
-/// A pulse, based on a timestamp that may be a duration in the future or a realtime timestamp.
-struct Pulse<Next> {
+pub mod compensation;
+pub mod interrupter;
+pub mod jitter;
+pub mod limiter;
+pub mod midi;
+pub mod routing;
+pub mod soft_start;
+pub mod thermal;
+
+/// A pulse train, with the next pulse relative to the start of the next buffer.
+#[derive(Clone)]
+struct Pulse {
     period: Duration,
-    next: Next,
+    next: Duration,
+    amplitude: u16,
+    /// Width of this pulse, as a fraction of the width set by the pulse width control
+    width: ScalingFactor<u16>,
+    /// Outputs to fire this pulse on
+    channels: Channels,
+    /// Number of sub-pulses to fire for this pulse
+    sub_pulses: u8,
 }
 
+/// Compute pulse timings from voices.
+///
+/// `max_amplitude` is the amplitude of the loudest possible peak, which fires pulses at the full width set by the pulse width control,
+/// and `noise_floor` is the amplitude of the quietest possible peak.
 #[inline(never)]
-pub fn schedule_pulses(
-    peaks: &Vec<Peak, { config::fft::analysis::MAX_PEAKS }>,
+pub fn schedule_pulses<'a>(
+    voices: impl Iterator<Item = &'a Voice>,
+    max_amplitude: u16,
+    noise_floor: u16,
     pulses_out: &mut UnadjustedPulses,
 ) {
-    pulses_out.pulses.replace_with_mapped(peaks, |peak| {
+    // guaranteed not to overflow since both sides have the same capacity (MAX_VOICES)
+    pulses_out.pulses.clear();
+    pulses_out.pulses.extend(voices.map(|voice| {
+        let peak = voice.peak();
         let period = peak.period();
         let phase_offset = peak.phase_offset();
+        let width = width(voice, max_amplitude, noise_floor);
         Pulse {
             period,
             next: phase_offset + period,
+            amplitude: peak.amplitude(),
+            width,
+            channels: routing::channels(voice),
+            sub_pulses: sub_pulses(width),
+        }
+    }));
+    pulses_out.burst = None;
+}
+
+/// Map a voice to a pulse width, via `WIDTH_CURVE`, `COMPENSATION_CURVE`, and its envelope.
+fn width(voice: &Voice, max_amplitude: u16, floor: u16) -> ScalingFactor<u16> {
+    let peak = voice.peak();
+
+    // Step 1: normalize amplitude from the noise floor to the loudest possible peak
+    let x = if max_amplitude <= floor {
+        u32::from(u16::MAX)
+    } else {
+        u32::from(peak.amplitude().saturating_sub(floor)) * u32::from(u16::MAX)
+            / u32::from(max_amplitude - floor)
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    let x = x.min(u32::from(u16::MAX)) as u16;
+
+    // Step 2: apply curve
+    let width = match config::pulse::WIDTH_CURVE {
+        WidthCurve::Constant => u16::MAX,
+        WidthCurve::Linear => x,
+        WidthCurve::Sqrt => amplitude_sqrt(u32::from(x) * u32::from(u16::MAX)),
+        WidthCurve::Square => x.scale_by(ScalingFactor::from_raw(x)),
+    };
+
+    // Step 3: apply frequency compensation
+    let width = if config::pulse::COMPENSATION {
+        width.scale_by(compensation::factor(peak.freq()))
+    } else {
+        width
+    };
+
+    // Step 4: apply envelope
+    let width = width.scale_by(voice.level());
+
+    ScalingFactor::from_raw(width)
+}
+
+/// Map a pulse width to a number of sub-pulses, so louder voices are fired with more sub-pulses, up to `MAX_SUB_PULSES`.
+fn sub_pulses(width: ScalingFactor<u16>) -> u8 {
+    // widths are split evenly between each number of sub-pulses
+    let extra = u16::from(config::pulse::MAX_SUB_PULSES).scale_by(width);
+    (1 + extra).truncate()
+}
+
+/// Phase of a pulse train generated independently of the audio input, so its timing stays consistent across buffers.
+pub struct Phase {
+    /// Time from the start of the next buffer until the next pulse
+    offset: Duration,
+}
+
+impl Phase {
+    pub const fn new() -> Self {
+        Self {
+            offset: Duration::from_ticks(0),
         }
-    });
+    }
+
+    /// Compute the first pulse of the next buffer, for a pulse train with the given period,
+    /// and advance to the following buffer.
+    fn advance(&mut self, period: Duration) -> Duration {
+        // Step 1: keep the phase of the previous pulse train, in case the period changed
+        let offset = self.offset.ticks() % period.ticks();
+
+        // Step 2: compute offset of the first pulse in the following buffer
+        let buf = config::adc::BUF_DURATION.ticks();
+        self.offset = Duration::from_ticks(match buf.checked_sub(offset) {
+            Some(remaining) => (period.ticks() - remaining % period.ticks()) % period.ticks(),
+            // the period is longer than a buffer, and the next pulse isn't in this buffer
+            None => offset - buf,
+        });
+
+        // delayed by the scheduling offset, so a pulse right at the start of the buffer isn't dropped
+        config::pulse::SCHEDULING_OFFSET + Duration::from_ticks(offset)
+    }
 }
 
-/// Holds pulses which contain their phase offset + period,
-/// but need to be adjusted by adding a start timestamp.
+/// A pulse consumed from the pulse train, along with any pulses merged into it.
+pub struct Consumed {
+    /// Width of the widest pulse consumed, as a fraction of the width set by the pulse width control
+    width: ScalingFactor<u16>,
+    /// Number of pulses merged into this one
+    merged: usize,
+    /// Outputs to fire this pulse on, including those of merged pulses
+    channels: Channels,
+    /// Number of sub-pulses to fire, the most of any pulse consumed
+    sub_pulses: u8,
+}
+
+impl Consumed {
+    /// Compute the width to fire this pulse with, given the width set by the pulse width control.
+    pub fn width(&self, max_width: PulseDuration) -> PulseDuration {
+        // quietest pulses are fired at the minimum width
+        let min_width = config::pulse::DURATION_RANGE.start.min(max_width);
+        let extra = (max_width - min_width).ticks().scale_by(self.width);
+        let width = min_width + PulseDuration::from_ticks(extra);
+        widen_for_merged(width, self.merged)
+    }
+
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+
+    /// Sub-pulses to fire, each at the width returned by `width`.
+    pub fn repeat(&self) -> Repeat<{ config::clk::TIM1CLK_HZ }> {
+        Repeat {
+            count: self.sub_pulses,
+            spacing: config::pulse::SUB_PULSE_SPACING,
+        }
+    }
+}
+
+/// Widen a pulse to account for other pulses merged into it.
+fn widen_for_merged(width: PulseDuration, merged: usize) -> PulseDuration {
+    let extra = width.ticks().scale_by(config::pulse::MERGE_WIDENING);
+    #[allow(clippy::cast_possible_truncation)]
+    let extra = extra.saturating_mul(merged as u32);
+    let width = PulseDuration::from_ticks(width.ticks().saturating_add(extra));
+    width.min(config::pulse::DURATION_RANGE.end)
+}
+
+/// Burst gating, where pulses are only fired during the first `on` of every `on + off`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Burst {
+    pub on: Duration,
+    pub off: Duration,
+}
+
+impl Burst {
+    pub fn period(&self) -> Duration {
+        self.on + self.off
+    }
+}
+
+/// Holds pulse trains which contain their phase offset + period,
+/// relative to the start of the next buffer.
 pub struct UnadjustedPulses {
-    pulses: Vec<Pulse<Duration>, { config::fft::analysis::MAX_PEAKS }>,
+    pulses: Vec<Pulse, { config::pulse::MAX_VOICES }>,
+    burst: Option<Burst>,
 }
 
 impl UnadjustedPulses {
     pub const fn new() -> Self {
-        Self { pulses: Vec::new() }
+        Self {
+            pulses: Vec::new(),
+            burst: None,
+        }
     }
 }
 
-/// Holds pulses which are scheduled based on a realtime timestamp.
-pub struct Pulses {
-    pulses: Vec<Pulse<Instant>, { config::fft::analysis::MAX_PEAKS }>,
+/// Counts of pulses which collided with other pulses, for telemetry.
+///
+/// These wrap around on overflow.
+#[derive(Copy, Clone, Format)]
+pub struct Collisions {
+    /// Pulses which were discarded
+    pub dropped: u32,
+    /// Pulses which were merged into another pulse
+    pub merged: u32,
 }
 
-impl Pulses {
+impl Collisions {
     pub const fn new() -> Self {
-        Self { pulses: Vec::new() }
+        Self {
+            dropped: 0,
+            merged: 0,
+        }
     }
+}
+
+/// Timelines cover this long after the start of their buffer,
+/// so pulses continue even if the next buffer is swapped in slightly late.
+const TIMELINE_HORIZON: Duration = Duration::from_ticks(
+    config::adc::BUF_DURATION.ticks() + config::pulse::TIMELINE_MARGIN.ticks(),
+);
+
+/// Maximum number of pulses in a timeline, since pulses are at least `COLLISION_WINDOW` apart.
+const TIMELINE_LEN: usize =
+    (TIMELINE_HORIZON.ticks() / config::pulse::COLLISION_WINDOW.ticks()) as usize + 1;
 
-    pub fn replace_with_adjusted(&mut self, unadjusted: &UnadjustedPulses, at: Instant) {
-        self.pulses
-            .replace_with_mapped(&unadjusted.pulses, |pulse| Pulse {
-                period: pulse.period,
-                next: at + pulse.next,
-            })
+const _: () = assert!(config::pulse::MAX_VOICES <= u8::MAX as usize);
+
+/// A pulse in a timeline.
+///
+/// This is packed, since timelines are large.
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct Entry {
+    /// Time from the start of the buffer
+    offset: Duration,
+    /// Width of the widest pulse, as a fraction of the width set by the pulse width control
+    width: ScalingFactor<u16>,
+    /// Number of pulses merged into this one
+    ///
+    /// This is at most `MAX_VOICES`, so it's stored in a `u8` to keep timelines small.
+    merged: u8,
+    /// Outputs to fire this pulse on, including those of merged pulses
+    channels: Channels,
+    /// Number of sub-pulses to fire, the most of any pulse merged into this one
+    sub_pulses: u8,
+}
+
+/// Pulses to fire during one buffer, relative to the start of the buffer,
+/// sorted and with collisions already resolved.
+pub struct Timeline {
+    entries: Deque<Entry, TIMELINE_LEN>,
+    burst: Option<Burst>,
+}
+
+const _: () = assert!(
+    core::mem::size_of::<Entry>() <= 9,
+    "timeline entries should stay packed"
+);
+
+// Timelines are double-buffered, and their length is the worst case (pulses `COLLISION_WINDOW` apart),
+// so they can't be shrunk without risking overflow; instead, bound their share of the 20 KiB of RAM.
+// (currently 302 entries, about 2.7 KiB each)
+const _: () = assert!(
+    2 * core::mem::size_of::<Timeline>() <= 6 * 1024,
+    "timelines should use at most 6 KiB of RAM, increase COLLISION_WINDOW or reduce TIMELINE_MARGIN"
+);
+
+impl Timeline {
+    pub const fn new() -> Self {
+        Self {
+            entries: Deque::new(),
+            burst: None,
+        }
     }
+}
+
+/// Merge the pulse trains of all frequencies into a timeline, resolving collisions.
+///
+/// This moves as much work as possible out of `fire_pulse`, which only has to pop the next pulse from the timeline.
+#[inline(never)]
+pub fn build_timeline(
+    pulses: &UnadjustedPulses,
+    collisions: &mut Collisions,
+    timeline_out: &mut Timeline,
+) {
+    let mut trains = pulses.pulses.clone();
+    let mut last: Option<Duration> = None;
+
+    timeline_out.entries.clear();
+    timeline_out.burst = pulses.burst;
 
-    /// Consume a pulse scheduled for a specific instant, and reschedule the relevant frequencies.
-    pub fn try_consume_pulse(&mut self, at: Instant) -> Result<(), ()> {
-        let mut found_any_matching = false;
-        for pulse in &mut self.pulses {
+    loop {
+        // Step 1: find earliest pulse
+        let next = match trains.iter().map(|pulse| pulse.next).min() {
+            Some(next) if next < TIMELINE_HORIZON => next,
+            _ => break,
+        };
+
+        // Step 2: discard pulses which are too close to schedule (or too close to the previous pulse)
+        let too_close = match last {
+            Some(last) => next < last + config::pulse::COLLISION_WINDOW,
+            None => next < config::pulse::SCHEDULING_OFFSET,
+        };
+        if too_close {
+            let dropped = reschedule_where(&mut trains, |pulse| pulse.next == next);
+            count(&mut collisions.dropped, dropped);
+            continue;
+        }
+
+        // Step 3: resolve collisions with later pulses
+        let collides = |pulse: &Pulse| pulse.next - next < config::pulse::COLLISION_WINDOW;
+        let at = match config::pulse::COLLISION_POLICY {
+            CollisionPolicy::Merge => next,
+            CollisionPolicy::PreferLoudest => trains
+                .iter()
+                .filter(|pulse| collides(pulse))
+                .max_by_key(|pulse| pulse.amplitude)
+                .map(|pulse| pulse.next)
+                .unwrap_or_else(|| panic!("can't find pulse that exists (impossible)")),
+        };
+        let mut width = ScalingFactor::from_raw(0);
+        let mut channels = Channels::NONE;
+        let mut sub_pulses = 1;
+        let mut merged = 0;
+        let mut dropped = 0;
+        reschedule_where(&mut trains, |pulse| {
+            if !collides(pulse) {
+                return false;
+            }
+            // pulses from different frequencies at exactly the same instant are not a collision
             if pulse.next == at {
-                found_any_matching = true;
-                pulse.next += pulse.period;
+                width = width.max(pulse.width);
+                channels = channels.union(pulse.channels);
+                sub_pulses = sub_pulses.max(pulse.sub_pulses);
+            } else if config::pulse::COLLISION_POLICY == CollisionPolicy::Merge {
+                width = width.max(pulse.width);
+                channels = channels.union(pulse.channels);
+                sub_pulses = sub_pulses.max(pulse.sub_pulses);
+                merged += 1;
+            } else {
+                dropped += 1;
             }
+            true
+        });
+        count(&mut collisions.merged, merged);
+        count(&mut collisions.dropped, dropped);
+
+        // Step 4: append to timeline
+        let entry = Entry {
+            offset: at,
+            width,
+            merged: merged.truncate(),
+            channels,
+            sub_pulses,
+        };
+        if let Err(_) = timeline_out.entries.push_back(entry) {
+            defmt::warn!("Timeline overflowed (impossible)");
+            break;
         }
-        if found_any_matching {
-            Ok(())
-        } else {
-            Err(())
+        last = Some(at);
+    }
+}
+
+/// Reschedule the next pulse of each frequency matching the predicate,
+/// returning the number of frequencies rescheduled.
+fn reschedule_where(
+    trains: &mut Vec<Pulse, { config::pulse::MAX_VOICES }>,
+    mut f: impl FnMut(&Pulse) -> bool,
+) -> usize {
+    let mut rescheduled = 0;
+    for pulse in trains {
+        if f(pulse) {
+            rescheduled += 1;
+            pulse.next += pulse.period;
         }
     }
+    rescheduled
+}
 
-    /// Get the timestamp of the next pulse, if any are scheduled.
-    pub fn next_pulse(&mut self, after: Instant) -> Option<Instant> {
-        loop {
-            let (offset, next_pulse) = self
-                .pulses
-                .iter()
-                .map(|pulse| {
-                    let after_ticks = after.ticks();
-                    let pulse_ticks = pulse.next.ticks();
-                    // handle tick count wrapping, e.g.
-                    //
-                    // |-*---*----*-------------*---|
-                    //   ^   ^    ^      ^      ^
-                    //   2   3    4    after    1
-                    let offset = Duration::from_ticks(pulse_ticks.wrapping_sub(after_ticks));
-                    (offset, pulse.next)
-                })
-                .min_by_key(|(offset, _)| *offset)?;
+fn count(counter: &mut u32, n: usize) {
+    #[allow(clippy::cast_possible_truncation)]
+    let n = n as u32;
+    *counter = counter.wrapping_add(n);
+}
+
+/// Holds the timeline of pulses currently being fired, based on a realtime timestamp.
+pub struct Pulses {
+    timeline: &'static mut Timeline,
+    /// Start of the buffer the timeline is relative to
+    start: Instant,
+    /// Burst gating, and the start of the current burst
+    burst: Option<(Burst, Instant)>,
+}
+
+impl Pulses {
+    pub fn new(timeline: &'static mut Timeline) -> Self {
+        Self {
+            timeline,
+            start: Instant::from_ticks(0),
+            burst: None,
+        }
+    }
 
-            if offset < config::pulse::SCHEDULING_OFFSET {
-                // too short interval--discard this pulse and retry
-                self.try_consume_pulse(next_pulse)
-                    .unwrap_or_else(|_| panic!("can't find pulse that exists (impossible)"));
-                continue;
+    /// Swap in the next timeline, starting at the given instant.
+    ///
+    /// The previous timeline is swapped out into `next`, so it can be reused for the following buffer.
+    pub fn swap_timeline(&mut self, next: &mut &'static mut Timeline, at: Instant) {
+        core::mem::swap(&mut self.timeline, next);
+        self.start = at;
+        // keep the timing of an ongoing burst, so bursts continue across buffers
+        // (including when only the on-time changes, since it follows a control)
+        self.burst = match (self.timeline.burst, self.burst) {
+            (Some(burst), Some((prev_burst, start))) if burst.period() == prev_burst.period() => {
+                Some((burst, start))
             }
+            (Some(burst), _) => Some((burst, at)),
+            (None, _) => None,
+        };
+    }
 
-            break Some(next_pulse);
+    /// Whether a pulse at a specific instant falls in the "off" part of a burst, so should not be fired.
+    pub fn is_burst_off(&mut self, at: Instant) -> bool {
+        let (burst, start) = match &mut self.burst {
+            Some(burst) => burst,
+            None => return false,
+        };
+        let cycle = burst.period().ticks();
+        match at.checked_duration_since(*start) {
+            Some(elapsed) => {
+                // move start forward to the current burst, so it doesn't wrap around
+                let elapsed_in_cycle = elapsed.ticks() % cycle;
+                *start += Duration::from_ticks(elapsed.ticks() - elapsed_in_cycle);
+                elapsed_in_cycle >= burst.on.ticks()
+            }
+            // before the first burst starts
+            None => false,
         }
     }
+
+    /// Consume the pulse scheduled for a specific instant, which must be the next pulse in the timeline.
+    pub fn try_consume_pulse(&mut self, at: Instant) -> Result<Consumed, ()> {
+        match self.timeline.entries.front() {
+            Some(entry) if self.start + entry.offset == at => {
+                let entry = *entry;
+                self.timeline.entries.pop_front();
+                Ok(Consumed {
+                    width: entry.width,
+                    merged: usize::from(entry.merged),
+                    channels: entry.channels,
+                    sub_pulses: entry.sub_pulses,
+                })
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Discard the remaining pulses in the current timeline.
+    pub fn clear(&mut self) {
+        self.timeline.entries.clear();
+    }
+
+    /// Whether the next pulse is overdue by more than `STALL_TIMEOUT`,
+    /// meaning that `fire_pulse` has stopped advancing through the timeline.
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        match self.next_pulse() {
+            Some(next_pulse) => match now.checked_duration_since(next_pulse) {
+                Some(overdue) => overdue > config::safety::STALL_TIMEOUT,
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Get the timestamp of the next pulse, if any remain in the timeline.
+    pub fn next_pulse(&self) -> Option<Instant> {
+        let entry = self.timeline.entries.front()?;
+        Some(self.start + entry.offset)
+    }
 }
@@ -0,0 +1,32 @@
+use crate::config;
+use crate::math::{ScaleBy, ScalingFactor};
+use fugit::Hertz;
+
+/// Look up the pulse width multiplier for a frequency,
+/// interpolating linearly between points of `COMPENSATION_CURVE` and clamping beyond its ends.
+pub fn factor(freq: Hertz<u32>) -> ScalingFactor<u16> {
+    let freq = freq.to_Hz();
+    let curve = &config::pulse::COMPENSATION_CURVE;
+
+    // Step 1: find the first point above this frequency
+    let upper = curve.iter().position(|&(f, _)| u32::from(f) > freq);
+
+    // Step 2: interpolate between it and the previous point
+    match upper {
+        None => curve[curve.len() - 1].1,
+        Some(0) => curve[0].1,
+        Some(i) => {
+            let (lo_freq, lo_factor) = curve[i - 1];
+            let (hi_freq, hi_factor) = curve[i];
+            let lo = i32::from(u16::MAX.scale_by(lo_factor));
+            let hi = i32::from(u16::MAX.scale_by(hi_factor));
+            #[allow(clippy::cast_possible_wrap)]
+            let num = (freq - u32::from(lo_freq)) as i32;
+            let denom = i32::from(hi_freq - lo_freq);
+            let factor = lo + (hi - lo) * num / denom;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let factor = factor as u16;
+            ScalingFactor::from_raw(factor)
+        }
+    }
+}
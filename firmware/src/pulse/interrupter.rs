@@ -0,0 +1,45 @@
+use crate::config;
+use crate::math::ScalingFactor;
+use crate::pulse::{routing, Burst, Phase, Pulse, UnadjustedPulses};
+use crate::time::Duration;
+use fugit::Hertz;
+
+/// Generates a fixed-rate pulse train, independent of the audio input, for the fixed rate and burst modes.
+pub struct Interrupter {
+    phase: Phase,
+}
+
+impl Interrupter {
+    pub const fn new() -> Self {
+        Self {
+            phase: Phase::new(),
+        }
+    }
+
+    /// Compute pulse timings for the next buffer.
+    ///
+    /// Pulses are fired on all outputs, at the full width set by the pulse width control,
+    /// and continue with consistent timing across buffers.
+    #[inline(never)]
+    pub fn schedule(
+        &mut self,
+        rate: Hertz<u32>,
+        burst: Option<Burst>,
+        pulses_out: &mut UnadjustedPulses,
+    ) {
+        let period: Duration = rate.into_duration();
+
+        pulses_out.pulses.clear();
+        if let Err(_) = pulses_out.pulses.push(Pulse {
+            period,
+            next: self.phase.advance(period),
+            amplitude: u16::MAX,
+            width: ScalingFactor::ONE,
+            channels: routing::ALL,
+            sub_pulses: config::pulse::MAX_SUB_PULSES,
+        }) {
+            defmt::warn!("Interrupter pulses overflowed (impossible)");
+        }
+        pulses_out.burst = burst;
+    }
+}
@@ -0,0 +1,89 @@
+use crate::config;
+use crate::math::Truncate;
+use crate::time::{Duration, Instant};
+
+const BUCKETS: usize = config::debug::JITTER_HISTOGRAM_BUCKETS;
+
+const BUCKET_WIDTH: u32 = config::debug::JITTER_HISTOGRAM_BUCKET_WIDTH.ticks();
+
+/// Statistics of how late pulses are fired, relative to the instant they were scheduled for.
+pub struct Jitter {
+    min: u32,
+    max: u32,
+    /// Sum of all lateness, in `Duration` ticks
+    total: u64,
+    count: u32,
+    /// Number of pulses in each bucket of `JITTER_HISTOGRAM_BUCKET_WIDTH`, with the last bucket including all later pulses
+    histogram: [u32; BUCKETS],
+}
+
+impl Jitter {
+    pub const fn new() -> Self {
+        Self {
+            min: u32::MAX,
+            max: 0,
+            total: 0,
+            count: 0,
+            histogram: [0; BUCKETS],
+        }
+    }
+
+    /// Record a pulse which was scheduled for `scheduled`, and actually fired at `fired`.
+    pub fn record(&mut self, scheduled: Instant, fired: Instant) {
+        // pulses should never fire early, but if they do, treat it as no jitter
+        let late = fired
+            .checked_duration_since(scheduled)
+            .map_or(0, |late| late.ticks());
+
+        self.min = self.min.min(late);
+        self.max = self.max.max(late);
+        self.total = self.total.saturating_add(u64::from(late));
+        self.count = self.count.saturating_add(1);
+
+        let bucket = usize::try_from(late / BUCKET_WIDTH).unwrap_or(usize::MAX);
+        let bucket = bucket.min(BUCKETS - 1);
+        self.histogram[bucket] = self.histogram[bucket].saturating_add(1);
+    }
+
+    /// Take the statistics recorded so far, resetting them.
+    ///
+    /// This is cheap, so it can be done while holding the lock, and logging can happen afterwards.
+    pub fn take(&mut self) -> Self {
+        core::mem::replace(self, Self::new())
+    }
+}
+
+pub fn log_jitter_prelude() {
+    if config::debug::LOG_PULSE_JITTER {
+        defmt::println!(".vz 3 cn Pulse Jitter");
+        defmt::println!(".vz 3 xn Lateness (ns)");
+        defmt::println!(".vz 3 yn Pulses");
+        let mut lateness = [0u32; BUCKETS];
+        for (i, late) in lateness.iter_mut().enumerate() {
+            let i: u16 = i.truncate();
+            *late = Duration::from_ticks(BUCKET_WIDTH * u32::from(i)).to_nanos();
+        }
+        defmt::println!(".vz 3 xs {}", lateness);
+    }
+}
+
+pub fn log_jitter(jitter: &Jitter) {
+    if config::debug::LOG_PULSE_JITTER {
+        if jitter.count == 0 {
+            defmt::println!("Pulse jitter: no pulses fired");
+        } else {
+            let min = Duration::from_ticks(jitter.min).to_nanos();
+            let max = Duration::from_ticks(jitter.max).to_nanos();
+            let mean = jitter.total / u64::from(jitter.count);
+            let mean = Duration::from_ticks(u32::try_from(mean).unwrap_or(u32::MAX)).to_nanos();
+            defmt::println!(
+                "Pulse jitter: min = {} ns, max = {} ns, mean = {} ns ({} pulses)",
+                min,
+                max,
+                mean,
+                jitter.count
+            );
+        }
+        defmt::println!(".vz 3 ys {}", jitter.histogram);
+    }
+}
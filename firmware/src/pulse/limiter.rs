@@ -0,0 +1,303 @@
+use crate::config;
+use crate::hal::tim::Repeat;
+use crate::math::const_scale_by_u32_u16;
+use crate::time::{Duration, Instant, PulseDuration};
+use heapless::Deque;
+
+/// Maximum total on-time within one `DUTY_CYCLE_WINDOW`.
+const MAX_ON_TIME: Duration = Duration::from_ticks(const_scale_by_u32_u16(
+    config::pulse::DUTY_CYCLE_WINDOW.ticks(),
+    config::pulse::MAX_DUTY_CYCLE,
+));
+
+/// Maximum number of pulses that can possibly be fired within one `DUTY_CYCLE_WINDOW`,
+/// since each pulse must be followed by at least `MIN_OFF_TIME`.
+const MAX_PULSES_IN_WINDOW: usize =
+    (config::pulse::DUTY_CYCLE_WINDOW.ticks() / config::pulse::MIN_OFF_TIME.ticks()) as usize + 1;
+
+const _: () = assert!(
+    config::pulse::MIN_OFF_TIME.ticks() < config::pulse::DUTY_CYCLE_WINDOW.ticks(),
+    "min off-time should be shorter than the duty cycle window, so the last pulse is always remembered"
+);
+
+/// Limits pulses to a maximum duty cycle over a sliding window, and enforces a minimum off-time between pulses,
+/// and optionally a minimum interval between the starts of pulses.
+///
+/// This sits between the pulse scheduler and the pulse timer, so that no schedule can overheat the coil driver.
+pub struct Limiter {
+    /// Start time and on-time of each pulse fired within the last `DUTY_CYCLE_WINDOW`, oldest first
+    recent: Deque<(Instant, Duration), MAX_PULSES_IN_WINDOW>,
+    /// Total on-time of pulses in `recent`
+    on_time: Duration,
+    /// End of the last pulse fired (only meaningful if `recent` is not empty)
+    last_end: Instant,
+    /// Start of the last pulse fired, which may be older than the window
+    last_start: Option<Instant>,
+}
+
+impl Limiter {
+    pub const fn new() -> Self {
+        Self {
+            recent: Deque::new(),
+            on_time: Duration::from_ticks(0),
+            last_end: Instant::from_ticks(0),
+            last_start: None,
+        }
+    }
+
+    /// Limit a pulse (with all of its sub-pulses) about to be fired at `now`.
+    ///
+    /// Returns the width to fire each sub-pulse with, which may be shorter than requested if the duty cycle budget is nearly exhausted,
+    /// or `None` if the pulse must be dropped.
+    ///
+    /// Pulses starting less than `min_interval` after the start of the last pulse are dropped (zero disables this).
+    ///
+    /// If a width is returned, the pulse is considered to be fired, and counts against the budget.
+    pub fn limit(
+        &mut self,
+        now: Instant,
+        width: PulseDuration,
+        repeat: Repeat<{ config::clk::TIM1CLK_HZ }>,
+        min_interval: Duration,
+    ) -> Option<PulseDuration> {
+        let count = u32::from(repeat.count.max(1));
+
+        // Step 1: forget pulses which started before the window
+        while let Some(&(start, on_time)) = self.recent.front() {
+            match now.checked_duration_since(start) {
+                Some(age) if age < config::pulse::DUTY_CYCLE_WINDOW => break,
+                // outside the window (or so old that the timestamp wrapped)
+                _ => {
+                    self.recent.pop_front();
+                    self.on_time -= on_time;
+                }
+            }
+        }
+
+        // Step 2: enforce minimum off-time after the last pulse
+        if !self.recent.is_empty() {
+            match now.checked_duration_since(self.last_end) {
+                Some(off_time) if off_time >= config::pulse::MIN_OFF_TIME => {}
+                // too soon after the last pulse, or the last pulse is still in progress
+                _ => return None,
+            }
+        }
+
+        // Step 2.5: enforce minimum interval after the start of the last pulse
+        if let Some(last_start) = self.last_start {
+            match now.checked_duration_since(last_start) {
+                Some(interval) if interval < min_interval => return None,
+                // long enough after the last pulse (or so long that the timestamp wrapped)
+                _ => {}
+            }
+        }
+
+        // Step 3: shorten pulse to fit within remaining budget
+        let remaining: PulseDuration = (MAX_ON_TIME - self.on_time).convert();
+        let width = width.min(remaining / count);
+
+        // Step 4: drop pulse if it would be too short to be useful
+        if width < config::pulse::DURATION_RANGE.start {
+            return None;
+        }
+
+        // Step 5: record pulse
+        let on_time: Duration = (width * count).convert();
+        match self.recent.push_back((now, on_time)) {
+            Ok(()) => {
+                self.on_time += on_time;
+                let length: Duration = repeat.length(width).convert();
+                self.last_end = now + length;
+                self.last_start = Some(now);
+            }
+            // impossible, since the min off-time bounds the number of pulses in the window
+            Err(_) => return None,
+        }
+
+        Some(width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_WIDTH: PulseDuration = config::pulse::DURATION_RANGE.end;
+    const NO_INTERVAL: Duration = Duration::from_ticks(0);
+
+    fn repeat(count: u8) -> Repeat<{ config::clk::TIM1CLK_HZ }> {
+        Repeat {
+            count,
+            spacing: config::pulse::SUB_PULSE_SPACING,
+        }
+    }
+
+    /// Request max width pulses back to back, each as soon as the min off-time after the previous request allows,
+    /// returning the start and limited width of each.
+    fn back_to_back(
+        limiter: &mut Limiter,
+        repeat: Repeat<{ config::clk::TIM1CLK_HZ }>,
+        n: usize,
+    ) -> Vec<(Instant, Option<PulseDuration>)> {
+        let mut now = Instant::from_ticks(0);
+        (0..n)
+            .map(|_| {
+                let width = limiter.limit(now, MAX_WIDTH, repeat, NO_INTERVAL);
+                let length: Duration = repeat.length(width.unwrap_or(MAX_WIDTH)).convert();
+                let pulse = (now, width);
+                now = now + length + config::pulse::MIN_OFF_TIME;
+                pulse
+            })
+            .collect()
+    }
+
+    /// Total on-time of the pulses which started within the window ending at `end`.
+    fn on_time_in_window(
+        pulses: &[(Instant, Option<PulseDuration>)],
+        count: u8,
+        end: Instant,
+    ) -> Duration {
+        pulses
+            .iter()
+            .filter(|(start, _)| *start <= end && end - *start < config::pulse::DUTY_CYCLE_WINDOW)
+            .filter_map(|(_, width)| *width)
+            .map(|width| (width * u32::from(count.max(1))).convert())
+            .fold(Duration::from_ticks(0), |total, on_time| total + on_time)
+    }
+
+    #[test]
+    fn duty_cycle_budget_is_never_exceeded() {
+        for count in [1, 3] {
+            let mut limiter = Limiter::new();
+            let pulses = back_to_back(&mut limiter, repeat(count), 2000);
+
+            assert!(pulses.iter().any(|(_, width)| width.is_none()));
+            for &(start, _) in &pulses {
+                assert!(on_time_in_window(&pulses, count, start) <= MAX_ON_TIME);
+            }
+        }
+    }
+
+    #[test]
+    fn budget_recovers_once_pulses_leave_the_window() {
+        let mut limiter = Limiter::new();
+        let pulses = back_to_back(&mut limiter, repeat(1), 2000);
+
+        let first_dropped = pulses.iter().position(|(_, width)| width.is_none());
+        let first_dropped = first_dropped.expect("budget was never exhausted");
+        let (exhausted_at, _) = pulses[first_dropped];
+        let recovered_at = pulses[0].0 + config::pulse::DUTY_CYCLE_WINDOW;
+        assert!(pulses
+            .iter()
+            .filter(|(start, _)| *start >= exhausted_at && *start < recovered_at)
+            .all(|(_, width)| width.is_none()));
+        assert!(pulses
+            .iter()
+            .any(|(start, width)| *start >= recovered_at && *width == Some(MAX_WIDTH)));
+    }
+
+    #[test]
+    fn min_off_time() {
+        let mut limiter = Limiter::new();
+        let start = Instant::from_ticks(1000);
+        let width = limiter.limit(start, MAX_WIDTH, repeat(1), NO_INTERVAL);
+        assert_eq!(width, Some(MAX_WIDTH));
+
+        let end = start + MAX_WIDTH.convert();
+        let too_soon = end + config::pulse::MIN_OFF_TIME - Duration::from_ticks(1);
+        let during = start + Duration::from_ticks(1);
+        assert_eq!(
+            limiter.limit(during, MAX_WIDTH, repeat(1), NO_INTERVAL),
+            None
+        );
+        assert_eq!(
+            limiter.limit(too_soon, MAX_WIDTH, repeat(1), NO_INTERVAL),
+            None
+        );
+
+        // the dropped pulses above don't count as the last pulse
+        let soon_enough = end + config::pulse::MIN_OFF_TIME;
+        assert_eq!(
+            limiter.limit(soon_enough, MAX_WIDTH, repeat(1), NO_INTERVAL),
+            Some(MAX_WIDTH)
+        );
+    }
+
+    #[test]
+    fn min_off_time_follows_last_sub_pulse() {
+        let mut limiter = Limiter::new();
+        let start = Instant::from_ticks(1000);
+        let width = limiter.limit(start, MAX_WIDTH, repeat(3), NO_INTERVAL);
+        assert_eq!(width, Some(MAX_WIDTH));
+
+        let end = start + repeat(3).length(MAX_WIDTH).convert();
+        let too_soon = end + config::pulse::MIN_OFF_TIME - Duration::from_ticks(1);
+        assert_eq!(
+            limiter.limit(too_soon, MAX_WIDTH, repeat(1), NO_INTERVAL),
+            None
+        );
+        let soon_enough = end + config::pulse::MIN_OFF_TIME;
+        assert!(limiter
+            .limit(soon_enough, MAX_WIDTH, repeat(1), NO_INTERVAL)
+            .is_some());
+    }
+
+    #[test]
+    fn min_interval() {
+        let mut limiter = Limiter::new();
+        let start = Instant::from_ticks(1000);
+        let interval = config::pulse::MIN_OFF_TIME * 3;
+        assert!(limiter
+            .limit(start, MAX_WIDTH, repeat(1), interval)
+            .is_some());
+
+        let too_soon = start + interval - Duration::from_ticks(1);
+        assert_eq!(
+            limiter.limit(too_soon, MAX_WIDTH, repeat(1), interval),
+            None
+        );
+        assert!(limiter
+            .limit(start + interval, MAX_WIDTH, repeat(1), interval)
+            .is_some());
+    }
+
+    #[test]
+    fn back_to_back_max_width_pulses_are_shortened_then_dropped() {
+        for count in [1, 3] {
+            let mut limiter = Limiter::new();
+            let pulses = back_to_back(&mut limiter, repeat(count), 2000);
+
+            // every pulse fits in the window before the budget runs out
+            let max_on_time: PulseDuration = MAX_ON_TIME.convert();
+            let per_pulse = MAX_WIDTH * u32::from(count);
+            let full = max_on_time / per_pulse;
+            let spacing = repeat(count).length(MAX_WIDTH).convert() + config::pulse::MIN_OFF_TIME;
+            assert!(spacing * (full + 1) < config::pulse::DUTY_CYCLE_WINDOW);
+
+            // Step 1: full width pulses until the budget runs out
+            assert!(pulses[..full as usize]
+                .iter()
+                .all(|(_, width)| *width == Some(MAX_WIDTH)));
+
+            // Step 2: the next pulse uses up the rest of the budget, if it's long enough to be useful
+            let rest = (max_on_time - per_pulse * full) / u32::from(count);
+            let expected = if rest < config::pulse::DURATION_RANGE.start {
+                None
+            } else {
+                Some(rest)
+            };
+            assert_eq!(pulses[full as usize].1, expected);
+
+            // Step 3: then pulses are dropped until the first pulse leaves the window
+            let recovered_at = pulses[0].0 + config::pulse::DUTY_CYCLE_WINDOW;
+            assert!(pulses[full as usize + 1..]
+                .iter()
+                .take_while(|(start, _)| *start < recovered_at)
+                .all(|(_, width)| width.is_none()));
+
+            // the same schedule is limited the same way every time
+            let mut limiter = Limiter::new();
+            assert_eq!(back_to_back(&mut limiter, repeat(count), 2000), pulses);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config;
+use crate::hal::tim::Channels;
+use crate::math::ScalingFactor;
+use crate::midi::{self, Message, BEND_PER_SEMITONE, NOTE_FREQS_MILLIHZ};
+use crate::pulse::{sub_pulses, Phase, Pulse, UnadjustedPulses};
+use crate::time::Duration;
+use heapless::Vec;
+
+/// A note being played, from note-on until note-off.
+struct Note {
+    channel: u8,
+    note: u8,
+    velocity: u8,
+    phase: Phase,
+}
+
+/// Plays notes received over MIDI as pulse trains, for the MIDI mode.
+pub struct Synth {
+    /// Notes being played, oldest first
+    notes: Vec<Note, { config::pulse::MAX_VOICES }>,
+    /// Current pitch bend of each channel
+    bends: [i16; 16],
+}
+
+impl Synth {
+    pub const fn new() -> Self {
+        Self {
+            notes: Vec::new(),
+            bends: [0; 16],
+        }
+    }
+
+    /// Update the notes being played from a received message.
+    pub fn handle(&mut self, message: Message) {
+        match message {
+            Message::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => {
+                // a repeated note-on only changes the velocity, so the pulse train stays in phase
+                if let Some(playing) = self
+                    .notes
+                    .iter_mut()
+                    .find(|playing| playing.channel == channel && playing.note == note)
+                {
+                    playing.velocity = velocity;
+                    return;
+                }
+                if self.notes.is_full() {
+                    defmt::warn!("Too many MIDI notes, dropping oldest note");
+                    self.notes.remove(0);
+                }
+                if let Err(_) = self.notes.push(Note {
+                    channel,
+                    note,
+                    velocity,
+                    phase: Phase::new(),
+                }) {
+                    defmt::warn!("MIDI notes overflowed (impossible)");
+                }
+            }
+            Message::NoteOff { channel, note } => {
+                self.notes
+                    .retain(|playing| playing.channel != channel || playing.note != note);
+            }
+            Message::ControlChange {
+                channel,
+                controller: midi::ALL_SOUND_OFF | midi::ALL_NOTES_OFF,
+                ..
+            } => {
+                self.notes.retain(|playing| playing.channel != channel);
+            }
+            Message::ControlChange { .. } => {}
+            Message::PitchBend { channel, bend } => {
+                self.bends[usize::from(channel)] = bend;
+            }
+        }
+    }
+
+    /// Compute pulse timings for the next buffer.
+    ///
+    /// Each note fires at its exact (pitch bent) frequency, with a width set by its velocity,
+    /// on the output selected by its MIDI channel, and continues with consistent timing across buffers.
+    #[inline(never)]
+    pub fn schedule(&mut self, pulses_out: &mut UnadjustedPulses) {
+        // guaranteed not to overflow since both sides have the same capacity (MAX_VOICES)
+        pulses_out.pulses.clear();
+        for playing in &mut self.notes {
+            let period = period(playing.note, self.bends[usize::from(playing.channel)]);
+
+            let width = ScalingFactor::from_ratio(u16::from(playing.velocity), 127);
+            #[allow(clippy::modulo_one)]
+            let output = usize::from(playing.channel) % config::pulse::OUTPUT_CHANNELS;
+            if let Err(_) = pulses_out.pulses.push(Pulse {
+                period,
+                // the phase is kept when the pitch bend changes
+                next: playing.phase.advance(period),
+                amplitude: u16::from(playing.velocity) << 9,
+                width,
+                channels: Channels::single(output),
+                sub_pulses: sub_pulses(width),
+            }) {
+                defmt::warn!("MIDI pulses overflowed (impossible)");
+            }
+        }
+        pulses_out.burst = None;
+    }
+}
+
+/// Period of a note, with pitch bend applied.
+///
+/// Pitch bend linearly interpolates between adjacent semitones, which is within 2 cents of the exact frequency.
+fn period(note: u8, bend: i16) -> Duration {
+    // Step 1: find position in the table, in units of 1/8192 semitone
+    let range = i32::from(config::midi::PITCH_BEND_RANGE);
+    let position = i32::from(note) * BEND_PER_SEMITONE + i32::from(bend) * range;
+    let position = position.clamp(0, 127 * BEND_PER_SEMITONE);
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    let semitone = (position / BEND_PER_SEMITONE) as usize;
+    #[allow(clippy::cast_sign_loss)]
+    let fraction = (position % BEND_PER_SEMITONE) as u64;
+
+    // Step 2: interpolate between adjacent semitones
+    let low = NOTE_FREQS_MILLIHZ[semitone];
+    let high = NOTE_FREQS_MILLIHZ[(semitone + 1).min(127)];
+    #[allow(clippy::cast_sign_loss)]
+    let freq = u64::from(low) + u64::from(high - low) * fraction / BEND_PER_SEMITONE as u64;
+
+    // Step 3: convert to period
+    let period = u64::from(config::clk::SYSCLK_HZ) * 1000 / freq;
+    #[allow(clippy::cast_possible_truncation)]
+    let period = period as u32;
+    Duration::from_ticks(period)
+}
@@ -0,0 +1,30 @@
+use crate::config;
+use crate::config::pulse::ChannelRouting;
+use crate::hal::tim::Channels;
+use crate::voice::Voice;
+
+/// All pulse outputs in use.
+pub const ALL: Channels = Channels::first(config::pulse::OUTPUT_CHANNELS);
+
+/// Choose which pulse outputs a voice is fired on, via `CHANNEL_ROUTING`.
+pub fn channels(voice: &Voice) -> Channels {
+    match config::pulse::CHANNEL_ROUTING {
+        ChannelRouting::ByBand => {
+            let freq = voice.peak().freq().to_Hz();
+            let crossovers =
+                &config::pulse::CHANNEL_CROSSOVER_FREQS[..config::pulse::OUTPUT_CHANNELS - 1];
+            let band = crossovers
+                .iter()
+                .filter(|&&crossover| freq >= u32::from(crossover))
+                .count();
+            Channels::single(band)
+        }
+        ChannelRouting::RoundRobin => {
+            // with a single output, every voice is on the first channel
+            #[allow(clippy::modulo_one)]
+            let channel = voice.channel() % config::pulse::OUTPUT_CHANNELS;
+            Channels::single(channel)
+        }
+        ChannelRouting::Duplicate => ALL,
+    }
+}
@@ -0,0 +1,64 @@
+use crate::config;
+use crate::math::{ScaleBy, ScalingFactor};
+use crate::time::{Duration, PulseDuration};
+
+/// Number of buffers over which the ramp runs.
+const RAMP_BUFFERS: u16 = {
+    let buffers = config::pulse::SOFT_START_DURATION.ticks() / config::adc::BUF_DURATION.ticks();
+    assert!(buffers > 0 && buffers <= u16::MAX as u32);
+    // fits, as checked above
+    #[allow(clippy::cast_possible_truncation)]
+    let buffers = buffers as u16;
+    buffers
+};
+
+/// Ramps up the maximum pulse width (and optionally the maximum pulse rate) after power-up and re-arming,
+/// so output doesn't start at full power.
+pub struct SoftStart {
+    /// Number of buffers since the ramp started
+    elapsed: u16,
+}
+
+impl SoftStart {
+    pub const fn new() -> Self {
+        Self { elapsed: 0 }
+    }
+
+    /// Restart the ramp from zero.
+    pub fn restart(&mut self) {
+        if self.elapsed >= RAMP_BUFFERS {
+            defmt::info!("Soft-start restarted");
+        }
+        self.elapsed = 0;
+    }
+
+    /// Advance the ramp by one buffer.
+    ///
+    /// This must be called exactly once per buffer.
+    pub fn advance(&mut self) {
+        self.elapsed = self.elapsed.saturating_add(1);
+    }
+
+    /// Fraction of full output currently allowed.
+    fn factor(&self) -> ScalingFactor<u16> {
+        ScalingFactor::from_ratio(self.elapsed.min(RAMP_BUFFERS), RAMP_BUFFERS)
+    }
+
+    /// Scale down the maximum pulse width set by the control.
+    pub fn pulse_width(&self, width: PulseDuration) -> PulseDuration {
+        PulseDuration::from_ticks(width.ticks().scale_by(self.factor()))
+    }
+
+    /// Minimum interval between pulses, enforcing the ramped maximum pulse rate, or zero if the rate is not limited.
+    pub fn min_interval(&self) -> Duration {
+        let factor = self.factor();
+        match config::pulse::SOFT_START_MAX_RATE {
+            Some(max_rate) if factor < ScalingFactor::ONE => {
+                // avoid an infinite interval at the start of the ramp
+                let rate = max_rate.scale_by(factor).max(1);
+                Duration::from_ticks(config::clk::SYSCLK_HZ / u32::from(rate))
+            }
+            _ => Duration::from_ticks(0),
+        }
+    }
+}
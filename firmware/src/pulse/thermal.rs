@@ -0,0 +1,75 @@
+use crate::config;
+use crate::math::{const_scale_by_u32_u16, ScalingFactor};
+use crate::time::PulseDuration;
+
+const BUDGET: u32 = config::pulse::THERMAL_BUDGET.ticks();
+
+/// Accumulated on-time above which pulse widths start being scaled down.
+const DERATE_START: u32 = const_scale_by_u32_u16(BUDGET, config::pulse::THERMAL_DERATE_START);
+
+/// Fraction of accumulated on-time remaining after cooling for one buffer.
+///
+/// This approximates `exp(-t/tau)` as `tau/(tau+t)` (rounded up), which slightly underestimates cooling.
+const COOLING_FACTOR: u64 = {
+    let tau = config::pulse::THERMAL_TIME_CONSTANT.ticks() as u64;
+    let t = config::adc::BUF_DURATION.ticks() as u64;
+    (u16::MAX as u64 * tau + (tau + t - 1)) / (tau + t)
+};
+
+const _: () = assert!(
+    DERATE_START < BUDGET,
+    "derating should start before the budget is exhausted"
+);
+
+/// I²t-style thermal model of the coil driver.
+///
+/// Assuming roughly constant current through the bridge while it is on, the energy dissipated by each pulse is proportional to its width,
+/// so this accumulates pulse on-time, which decays exponentially as the bridge cools.
+pub struct Thermal {
+    /// Accumulated on-time, in `PulseDuration` ticks
+    energy: u32,
+}
+
+impl Thermal {
+    pub const fn new() -> Self {
+        Self { energy: 0 }
+    }
+
+    /// Cool down for the duration of one buffer.
+    ///
+    /// This must be called exactly once per buffer.
+    pub fn cool(&mut self) {
+        let energy = u64::from(self.energy) * COOLING_FACTOR / u64::from(u16::MAX);
+        #[allow(clippy::cast_possible_truncation)]
+        let energy = energy as u32;
+        self.energy = energy;
+    }
+
+    /// Accumulate the heat from firing a pulse.
+    pub fn heat(&mut self, width: PulseDuration) {
+        self.energy = self.energy.saturating_add(width.ticks());
+    }
+
+    /// Fraction of the thermal budget currently used.
+    pub fn load(&self) -> ScalingFactor<u16> {
+        let load = u64::from(self.energy.min(BUDGET)) * u64::from(u16::MAX) / u64::from(BUDGET);
+        #[allow(clippy::cast_possible_truncation)]
+        let load = load as u16;
+        ScalingFactor::from_raw(load)
+    }
+
+    /// Scale down a pulse width, progressively as the thermal budget is approached.
+    ///
+    /// Pulses are unaffected below `THERMAL_DERATE_START`, and scaled down to zero width when the budget is exhausted.
+    pub fn derate(&self, width: PulseDuration) -> PulseDuration {
+        if self.energy <= DERATE_START {
+            return width;
+        }
+
+        let remaining = u64::from(BUDGET.saturating_sub(self.energy));
+        let width = u64::from(width.ticks()) * remaining / u64::from(BUDGET - DERATE_START);
+        #[allow(clippy::cast_possible_truncation)]
+        let width = width as u32;
+        PulseDuration::from_ticks(width)
+    }
+}
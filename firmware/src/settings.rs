@@ -0,0 +1,383 @@
+use crate::config;
+use crate::config::fft::Window;
+use crate::time::PulseDuration;
+use core::fmt;
+use core::ops::{Range, RangeInclusive};
+
+/// Settings which can be changed at runtime over the command port, without reflashing.
+///
+/// These start out with the values from `config`, which remain the defaults.
+/// `swap_buffers` takes a copy once per buffer, so a change applies from the next buffer.
+#[derive(Clone)]
+pub struct Settings {
+    pub noise_floor: u16,
+    pub max_peaks: usize,
+    pub window: Window,
+    pub equalization: bool,
+    pub pulse_width_min: PulseDuration,
+    pub pulse_width_max: PulseDuration,
+    pub log_timing: bool,
+    pub log_control_values: bool,
+    pub log_fft_peaks: bool,
+    pub log_voices: bool,
+    pub log_thermal_load: bool,
+    pub log_stats: bool,
+}
+
+/// A setting, as named in commands.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Param {
+    NoiseFloor,
+    MaxPeaks,
+    Window,
+    Equalization,
+    PulseWidthMin,
+    PulseWidthMax,
+    LogTiming,
+    LogControlValues,
+    LogFftPeaks,
+    LogVoices,
+    LogThermalLoad,
+    LogStats,
+}
+
+/// Why a value couldn't be set.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SetError {
+    /// The value is outside the setting's range
+    OutOfRange,
+    /// The minimum pulse width must be less than the maximum
+    PulseWidthOrder,
+}
+
+const BOOL_RANGE: RangeInclusive<u32> = 0..=1;
+const BOOL_UNIT: &str = "(0 = off, 1 = on)";
+
+impl Param {
+    /// All settings, in the order they're listed.
+    pub const ALL: [Self; 12] = [
+        Self::NoiseFloor,
+        Self::MaxPeaks,
+        Self::Window,
+        Self::Equalization,
+        Self::PulseWidthMin,
+        Self::PulseWidthMax,
+        Self::LogTiming,
+        Self::LogControlValues,
+        Self::LogFftPeaks,
+        Self::LogVoices,
+        Self::LogThermalLoad,
+        Self::LogStats,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoiseFloor => "noise_floor",
+            Self::MaxPeaks => "max_peaks",
+            Self::Window => "window",
+            Self::Equalization => "equalization",
+            Self::PulseWidthMin => "pulse_width_min",
+            Self::PulseWidthMax => "pulse_width_max",
+            Self::LogTiming => "log_timing",
+            Self::LogControlValues => "log_control_values",
+            Self::LogFftPeaks => "log_fft_peaks",
+            Self::LogVoices => "log_voices",
+            Self::LogThermalLoad => "log_thermal_load",
+            Self::LogStats => "log_stats",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|param| param.name() == name)
+    }
+
+    pub fn unit(self) -> Unit {
+        match self {
+            Self::NoiseFloor => Unit::Text("amplitude"),
+            Self::MaxPeaks => Unit::Text("peaks"),
+            Self::Window => Unit::Window,
+            Self::PulseWidthMin | Self::PulseWidthMax => Unit::Text("ns"),
+            Self::Equalization
+            | Self::LogTiming
+            | Self::LogControlValues
+            | Self::LogFftPeaks
+            | Self::LogVoices
+            | Self::LogThermalLoad
+            | Self::LogStats => Unit::Text(BOOL_UNIT),
+        }
+    }
+
+    /// Values which are safe to set, regardless of other settings.
+    pub fn range(self) -> RangeInclusive<u32> {
+        let pulse_width = &config::pulse::DURATION_RANGE;
+        match self {
+            // up to the loudest possible peak with any window (since the window can be changed separately),
+            // as a higher noise floor would silence all peaks
+            Self::NoiseFloor => {
+                let max_amplitude = Window::ALL
+                    .into_iter()
+                    .map(config::fft::max_amplitude)
+                    .max();
+                1..=u32::from(max_amplitude.unwrap_or(u16::MAX))
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            Self::MaxPeaks => 1..=config::fft::analysis::MAX_PEAKS as u32,
+            #[allow(clippy::cast_possible_truncation)]
+            Self::Window => 0..=Window::ALL.len() as u32 - 1,
+            // the pulse width control must still be able to reach the re-arm width
+            Self::PulseWidthMin => {
+                pulse_width.start.to_nanos()..=config::safety::REARM_MAX_PULSE_WIDTH.to_nanos()
+            }
+            Self::PulseWidthMax => pulse_width.start.to_nanos()..=pulse_width.end.to_nanos(),
+            Self::Equalization
+            | Self::LogTiming
+            | Self::LogControlValues
+            | Self::LogFftPeaks
+            | Self::LogVoices
+            | Self::LogThermalLoad
+            | Self::LogStats => BOOL_RANGE,
+        }
+    }
+}
+
+/// Unit of a setting's value, as shown on the command port.
+#[derive(Copy, Clone)]
+pub enum Unit {
+    Text(&'static str),
+    /// Index into `Window::ALL`, shown as a list of window names
+    Window,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(text) => f.write_str(text),
+            Self::Window => {
+                f.write_str("(")?;
+                for (i, window) in Window::ALL.into_iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {}", i, window.name())?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl Settings {
+    /// Defaults, from `config`.
+    pub const fn new() -> Self {
+        Self {
+            noise_floor: config::fft::analysis::NOISE_FLOOR_AMPLITUDE,
+            max_peaks: config::fft::analysis::MAX_PEAKS,
+            window: config::fft::WINDOW,
+            equalization: config::fft::EQUALIZATION,
+            pulse_width_min: config::pulse::DURATION_RANGE.start,
+            pulse_width_max: config::pulse::DURATION_RANGE.end,
+            log_timing: config::debug::LOG_TIMING,
+            log_control_values: config::debug::LOG_CONTROL_VALUES,
+            log_fft_peaks: config::debug::LOG_FFT_PEAKS,
+            log_voices: config::debug::LOG_VOICES,
+            log_thermal_load: config::debug::LOG_THERMAL_LOAD,
+            log_stats: config::debug::LOG_STATS,
+        }
+    }
+
+    /// Pulse duration range when the pulse width control is set to minimum/maximum.
+    pub fn pulse_width(&self) -> Range<PulseDuration> {
+        self.pulse_width_min..self.pulse_width_max
+    }
+
+    pub fn get(&self, param: Param) -> u32 {
+        match param {
+            Param::NoiseFloor => u32::from(self.noise_floor),
+            #[allow(clippy::cast_possible_truncation)]
+            Param::MaxPeaks => self.max_peaks as u32,
+            #[allow(clippy::cast_possible_truncation)]
+            Param::Window => Window::ALL
+                .iter()
+                .position(|&window| window == self.window)
+                .unwrap_or(0) as u32,
+            Param::Equalization => u32::from(self.equalization),
+            Param::PulseWidthMin => self.pulse_width_min.to_nanos(),
+            Param::PulseWidthMax => self.pulse_width_max.to_nanos(),
+            Param::LogTiming => u32::from(self.log_timing),
+            Param::LogControlValues => u32::from(self.log_control_values),
+            Param::LogFftPeaks => u32::from(self.log_fft_peaks),
+            Param::LogVoices => u32::from(self.log_voices),
+            Param::LogThermalLoad => u32::from(self.log_thermal_load),
+            Param::LogStats => u32::from(self.log_stats),
+        }
+    }
+
+    /// Set a value, if it's in range and consistent with the other settings (otherwise nothing changes).
+    pub fn set(&mut self, param: Param, value: u32) -> Result<(), SetError> {
+        // Step 1: check the value on its own
+        if !param.range().contains(&value) {
+            return Err(SetError::OutOfRange);
+        }
+
+        // Step 2: apply the value to a copy
+        let mut new = self.clone();
+        #[allow(clippy::cast_possible_truncation)]
+        match param {
+            Param::NoiseFloor => new.noise_floor = value as u16,
+            Param::MaxPeaks => new.max_peaks = value as usize,
+            Param::Window => new.window = Window::ALL[value as usize],
+            Param::Equalization => new.equalization = value != 0,
+            Param::PulseWidthMin => new.pulse_width_min = pulse_duration_from_nanos(value),
+            Param::PulseWidthMax => new.pulse_width_max = pulse_duration_from_nanos(value),
+            Param::LogTiming => new.log_timing = value != 0,
+            Param::LogControlValues => new.log_control_values = value != 0,
+            Param::LogFftPeaks => new.log_fft_peaks = value != 0,
+            Param::LogVoices => new.log_voices = value != 0,
+            Param::LogThermalLoad => new.log_thermal_load = value != 0,
+            Param::LogStats => new.log_stats = value != 0,
+        }
+
+        // Step 3: check the copy against the other settings
+        if new.pulse_width_min >= new.pulse_width_max {
+            return Err(SetError::PulseWidthOrder);
+        }
+
+        *self = new;
+        Ok(())
+    }
+}
+
+/// Convert nanoseconds to a pulse duration, rounding down to whole timer ticks.
+fn pulse_duration_from_nanos(nanos: u32) -> PulseDuration {
+    let ticks = u64::from(nanos) * u64::from(config::clk::TIM1CLK_HZ) / 1_000_000_000;
+    #[allow(clippy::cast_possible_truncation)]
+    let ticks = ticks as u32;
+    PulseDuration::from_ticks(ticks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        for param in Param::ALL {
+            assert_eq!(Param::from_name(param.name()), Some(param));
+        }
+        assert_eq!(Param::from_name("nonsense"), None);
+        assert_eq!(Param::from_name(""), None);
+    }
+
+    #[test]
+    fn window_unit_lists_all_windows() {
+        assert_eq!(
+            Param::Window.unit().to_string(),
+            "(0 = rectangle, 1 = hamming, 2 = hann, 3 = blackman, 4 = blackman-harris, 5 = flat top, 6 = kaiser)"
+        );
+        assert_eq!(Param::MaxPeaks.unit().to_string(), "peaks");
+    }
+
+    #[test]
+    fn defaults_are_in_range() {
+        let settings = Settings::new();
+        for param in Param::ALL {
+            assert!(
+                param.range().contains(&settings.get(param)),
+                "{}",
+                param.name()
+            );
+        }
+    }
+
+    #[test]
+    fn range_limits() {
+        for param in Param::ALL {
+            let range = param.range();
+
+            // Step 1: both ends of the range are accepted (as long as the pulse widths stay in order)
+            for value in [*range.start(), *range.end()] {
+                let mut settings = Settings::new();
+                match settings.set(param, value) {
+                    Ok(()) => assert_eq!(settings.get(param), value, "{}", param.name()),
+                    Err(SetError::PulseWidthOrder) => {
+                        assert!(matches!(param, Param::PulseWidthMin | Param::PulseWidthMax))
+                    }
+                    Err(SetError::OutOfRange) => panic!("{} {} out of range", param.name(), value),
+                }
+            }
+
+            // Step 2: outside the range is rejected, without changing anything
+            let mut outside = vec![range.end() + 1, u32::MAX];
+            if *range.start() > 0 {
+                outside.push(range.start() - 1);
+            }
+            for value in outside {
+                let mut settings = Settings::new();
+                assert_eq!(
+                    settings.set(param, value),
+                    Err(SetError::OutOfRange),
+                    "{} {}",
+                    param.name(),
+                    value
+                );
+                assert_eq!(settings.get(param), Settings::new().get(param));
+            }
+        }
+    }
+
+    #[test]
+    fn pulse_width_order() {
+        let mut settings = Settings::new();
+        let min = settings.get(Param::PulseWidthMin);
+        assert_eq!(
+            settings.set(Param::PulseWidthMax, min),
+            Err(SetError::PulseWidthOrder)
+        );
+        assert_eq!(
+            settings.get(Param::PulseWidthMax),
+            Settings::new().get(Param::PulseWidthMax)
+        );
+
+        // the minimum can't be raised above the maximum either
+        let rearm = config::safety::REARM_MAX_PULSE_WIDTH.to_nanos();
+        assert_eq!(settings.set(Param::PulseWidthMax, rearm), Ok(()));
+        assert_eq!(
+            settings.set(Param::PulseWidthMin, rearm),
+            Err(SetError::PulseWidthOrder)
+        );
+        assert_eq!(settings.get(Param::PulseWidthMin), min);
+    }
+
+    #[test]
+    fn pulse_width_rounds_down_to_timer_ticks() {
+        let tick_nanos = 1_000_000_000_u32.div_ceil(config::clk::TIM1CLK_HZ);
+        let max = config::pulse::DURATION_RANGE.end.to_nanos();
+        let mut settings = Settings::new();
+        assert_eq!(settings.set(Param::PulseWidthMax, max - 1), Ok(()));
+        let rounded = settings.get(Param::PulseWidthMax);
+        assert!(rounded < max);
+        assert!(max - 1 - rounded < tick_nanos);
+    }
+
+    #[test]
+    fn window() {
+        let mut settings = Settings::new();
+        for (i, &window) in Window::ALL.iter().enumerate() {
+            let i = u32::try_from(i).unwrap();
+            assert_eq!(settings.set(Param::Window, i), Ok(()));
+            assert!(settings.window == window);
+            assert_eq!(settings.get(Param::Window), i);
+        }
+    }
+
+    #[test]
+    fn bools() {
+        let mut settings = Settings::new();
+        assert_eq!(settings.set(Param::LogStats, 1), Ok(()));
+        assert!(settings.log_stats);
+        assert_eq!(settings.set(Param::LogStats, 0), Ok(()));
+        assert!(!settings.log_stats);
+        assert_eq!(settings.set(Param::LogStats, 2), Err(SetError::OutOfRange));
+        assert!(!settings.log_stats);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::config;
+use crate::math::ScalingFactor;
+
+/// Number of consecutive silent buffers after which standby is entered.
+const TIMEOUT_BUFFERS: usize = config::standby::TIMEOUT_SECS * config::adc::BUFFERS_PER_SEC;
+
+/// Detects when the input has been silent for a while, to stop firing pulses from noise.
+pub struct Standby {
+    /// Number of consecutive buffers in which the input was silent
+    silent_for: usize,
+    in_standby: bool,
+}
+
+impl Standby {
+    pub const fn new() -> Self {
+        Self {
+            silent_for: 0,
+            in_standby: false,
+        }
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.in_standby
+    }
+
+    /// Update with the amplitude of the input, and the number of peaks found above the threshold.
+    ///
+    /// This must be called exactly once per buffer.
+    pub fn update(&mut self, amplitude: ScalingFactor<u16>, peaks: usize) {
+        if !config::standby::ENABLED {
+            return;
+        }
+
+        if self.in_standby {
+            // require a louder input to wake up, so noise near the silence threshold doesn't toggle standby
+            if amplitude >= config::standby::WAKE_AMPLITUDE && peaks > 0 {
+                defmt::info!("Leaving standby");
+                self.in_standby = false;
+                self.silent_for = 0;
+            }
+        } else if amplitude < config::standby::SILENCE_AMPLITUDE || peaks == 0 {
+            self.silent_for += 1;
+            if self.silent_for >= TIMEOUT_BUFFERS {
+                defmt::info!(
+                    "Input silent for {} seconds, entering standby",
+                    config::standby::TIMEOUT_SECS
+                );
+                self.in_standby = true;
+            }
+        } else {
+            self.silent_for = 0;
+        }
+    }
+}
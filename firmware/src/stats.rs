@@ -0,0 +1,208 @@
+use crate::math::ScalingFactor;
+use crate::time::Duration;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A running count of events, which can be incremented from any task without locking.
+///
+/// This wraps around on overflow.
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn take(&self) -> u32 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// The worst-case duration of some work, which can be updated from any task without locking.
+pub struct Worst(AtomicU32);
+
+impl Worst {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn record(&self, duration: Duration) {
+        self.0.fetch_max(duration.ticks(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_ticks(self.0.load(Ordering::Relaxed))
+    }
+
+    fn take(&self) -> Duration {
+        Duration::from_ticks(self.0.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// The latest value of some fraction, which can be updated from any task without locking.
+pub struct Level(AtomicU32);
+
+impl Level {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn set(&self, level: ScalingFactor<u16>) {
+        self.0.store(u32::from(level.raw()), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Buffers which were processed in time
+pub static PROCESSED_FRAMES: Counter = Counter::new();
+/// Buffers which did not finish processing before the next buffer was ready
+pub static LATE_FRAMES: Counter = Counter::new();
+/// Pulses which were fired
+pub static PULSES_FIRED: Counter = Counter::new();
+/// Pulses which were skipped due to the interlock, burst gating, the duty cycle limiter, or the thermal limit
+///
+/// Pulses dropped by the collision policy are counted separately, in the timeline's collision counts.
+pub static PULSES_DROPPED: Counter = Counter::new();
+/// Pulses which were missing from the timeline when their timer fired, so were dropped
+pub static PULSES_MISSING: Counter = Counter::new();
+/// Pulses which could not be scheduled because the previous one was still pending
+pub static SCHEDULE_OVERRUNS: Counter = Counter::new();
+/// Scheduled pulses which could not be cancelled when swapping buffers
+pub static CANCEL_FAILURES: Counter = Counter::new();
+/// Longest time taken by `swap_buffers`
+pub static WORST_SWAP_BUFFERS: Worst = Worst::new();
+/// Fraction of the thermal budget used, as of the last buffer
+pub static THERMAL_LOAD: Level = Level::new();
+
+/// The values of all statistics at one point in time.
+///
+/// This only contains plain integers, so it can be stored in the crash record.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Snapshot {
+    pub processed_frames: u32,
+    pub late_frames: u32,
+    pub pulses_fired: u32,
+    pub pulses_dropped: u32,
+    pub pulses_missing: u32,
+    pub schedule_overruns: u32,
+    pub cancel_failures: u32,
+    /// In `Duration` ticks
+    pub worst_swap_buffers: u32,
+    /// Raw `ScalingFactor<u16>`, where `u16::MAX` is the whole budget
+    pub thermal_load: u32,
+}
+
+impl Snapshot {
+    /// Read the current value of all statistics.
+    pub fn take() -> Self {
+        Self {
+            processed_frames: PROCESSED_FRAMES.get(),
+            late_frames: LATE_FRAMES.get(),
+            pulses_fired: PULSES_FIRED.get(),
+            pulses_dropped: PULSES_DROPPED.get(),
+            pulses_missing: PULSES_MISSING.get(),
+            schedule_overruns: SCHEDULE_OVERRUNS.get(),
+            cancel_failures: CANCEL_FAILURES.get(),
+            worst_swap_buffers: WORST_SWAP_BUFFERS.get().ticks(),
+            thermal_load: THERMAL_LOAD.get(),
+        }
+    }
+
+    /// Read the current value of all statistics, and reset them to zero.
+    ///
+    /// Each statistic is reset as it's read, so no events are lost.
+    /// Levels (the thermal load) are current values, so they aren't reset.
+    pub fn take_and_reset() -> Self {
+        Self {
+            processed_frames: PROCESSED_FRAMES.take(),
+            late_frames: LATE_FRAMES.take(),
+            pulses_fired: PULSES_FIRED.take(),
+            pulses_dropped: PULSES_DROPPED.take(),
+            pulses_missing: PULSES_MISSING.take(),
+            schedule_overruns: SCHEDULE_OVERRUNS.take(),
+            cancel_failures: CANCEL_FAILURES.take(),
+            worst_swap_buffers: WORST_SWAP_BUFFERS.take().ticks(),
+            thermal_load: THERMAL_LOAD.get(),
+        }
+    }
+
+    fn thermal_load_percent(&self) -> u32 {
+        self.thermal_load * 100 / u32::from(u16::MAX)
+    }
+
+    /// Write all statistics, one per line, for the command port.
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "processed_frames = {}\r", self.processed_frames)?;
+        writeln!(out, "late_frames = {}\r", self.late_frames)?;
+        writeln!(out, "pulses_fired = {}\r", self.pulses_fired)?;
+        writeln!(out, "pulses_dropped = {}\r", self.pulses_dropped)?;
+        writeln!(out, "pulses_missing = {}\r", self.pulses_missing)?;
+        writeln!(out, "schedule_overruns = {}\r", self.schedule_overruns)?;
+        writeln!(out, "cancel_failures = {}\r", self.cancel_failures)?;
+        writeln!(
+            out,
+            "worst_swap_buffers = {} us\r",
+            Duration::from_ticks(self.worst_swap_buffers).to_micros()
+        )?;
+        writeln!(out, "thermal_load = {} %\r", self.thermal_load_percent())
+    }
+
+    /// Log all statistics as a single line.
+    pub fn log(&self, prefix: &str) {
+        defmt::println!(
+            "{=str}: {} frames ({} late), {} pulses fired ({} dropped, {} missing), {} schedule overruns, {} cancel failures, worst swap_buffers {} us, thermal load {}%",
+            prefix,
+            self.processed_frames,
+            self.late_frames,
+            self.pulses_fired,
+            self.pulses_dropped,
+            self.pulses_missing,
+            self.schedule_overruns,
+            self.cancel_failures,
+            Duration::from_ticks(self.worst_swap_buffers).to_micros(),
+            self.thermal_load_percent(),
+        );
+    }
+}
+
+/// Dump all statistics, both as a log line and as status entries for the visualizer.
+pub fn log_stats() {
+    let snapshot = Snapshot::take();
+    snapshot.log("Stats");
+
+    let Snapshot {
+        processed_frames,
+        late_frames,
+        pulses_fired,
+        pulses_dropped,
+        pulses_missing,
+        schedule_overruns,
+        cancel_failures,
+        worst_swap_buffers,
+        thermal_load,
+    } = snapshot;
+    let worst_swap_buffers = Duration::from_ticks(worst_swap_buffers).to_micros();
+    let thermal_load = thermal_load * 100 / u32::from(u16::MAX);
+
+    defmt::println!(".vz st Processed frames {}", processed_frames);
+    defmt::println!(".vz st Late frames {}", late_frames);
+    defmt::println!(".vz st Pulses fired {}", pulses_fired);
+    defmt::println!(".vz st Pulses dropped {}", pulses_dropped);
+    defmt::println!(".vz st Pulses missing {}", pulses_missing);
+    defmt::println!(".vz st Schedule overruns {}", schedule_overruns);
+    defmt::println!(".vz st Cancel failures {}", cancel_failures);
+    defmt::println!(".vz st Worst swap_buffers (us) {}", worst_swap_buffers);
+    defmt::println!(".vz st Thermal load (%) {}", thermal_load);
+}
@@ -0,0 +1,180 @@
+use crate::config;
+use crate::fft::analysis::Peak;
+use crate::math::{ScaleBy, ScalingFactor};
+use heapless::Vec;
+
+pub mod allocator;
+pub mod arpeggiator;
+pub mod envelope;
+
+use envelope::Envelope;
+
+/// A frequency tracked across consecutive buffers.
+pub struct Voice {
+    /// Identifies this voice across buffers
+    id: u16,
+    peak: Peak,
+    envelope: Envelope,
+    /// Whether the peak was present in the current buffer
+    held: bool,
+    /// Number of buffers since this voice started, for priority
+    age: u16,
+    /// Whether this voice was chosen by the allocator to sound
+    allocated: bool,
+    /// Number of buffers since this voice was allocated, for hold time
+    allocated_for: u16,
+    /// Output channel assigned when this voice started, for round-robin routing
+    channel: usize,
+}
+
+impl Voice {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn peak(&self) -> &Peak {
+        &self.peak
+    }
+
+    pub fn level(&self) -> ScalingFactor<u16> {
+        self.envelope.level()
+    }
+
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    /// Whether the peak was present in the current buffer.
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Whether this voice is currently audible, i.e. allocated or releasing.
+    fn is_sounding(&self) -> bool {
+        self.level() > ScalingFactor::from_raw(0)
+    }
+
+    /// Whether a peak is close enough in frequency to continue this voice.
+    fn matches(&self, peak: &Peak) -> bool {
+        let freq = self.peak.freq().to_Hz();
+        let tolerance = freq.scale_by(config::pulse::VOICE_FREQ_TOLERANCE);
+        freq.abs_diff(peak.freq().to_Hz()) <= tolerance
+    }
+}
+
+/// Voices tracked across buffers, driven by the peaks found in each buffer.
+pub struct Voices {
+    voices: Vec<Voice, { config::pulse::MAX_VOICES }>,
+    /// Output channel to assign to the next voice which starts
+    next_channel: usize,
+    /// Id to assign to the next voice which starts (wrapping around on overflow)
+    next_id: u16,
+}
+
+impl Voices {
+    pub const fn new() -> Self {
+        Self {
+            voices: Vec::new(),
+            next_channel: 0,
+            next_id: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Voice> {
+        self.voices.iter()
+    }
+
+    /// Voices which are currently audible, i.e. allocated or releasing.
+    pub fn sounding(&self) -> impl Iterator<Item = &Voice> {
+        self.voices.iter().filter(|voice| voice.is_sounding())
+    }
+
+    /// Update voices with the peaks found in the current buffer.
+    #[inline(never)]
+    pub fn update(&mut self, peaks: &Vec<Peak, { config::fft::analysis::MAX_PEAKS }>) {
+        // Step 1: mark all voices as not held
+        for voice in &mut self.voices {
+            voice.held = false;
+        }
+
+        // Step 2: continue existing voices, or start new voices, from peaks
+        for peak in peaks {
+            let closest = self
+                .voices
+                .iter_mut()
+                .filter(|voice| !voice.held && voice.matches(peak))
+                .min_by_key(|voice| voice.peak.freq().to_Hz().abs_diff(peak.freq().to_Hz()));
+
+            if let Some(voice) = closest {
+                voice.peak = *peak;
+                voice.held = true;
+                continue;
+            }
+
+            if self.voices.is_full() {
+                // evict the quietest voice which is not held
+                // (there always is one, since there are more voices than peaks)
+                let quietest = self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, voice)| !voice.held)
+                    .min_by_key(|(_, voice)| voice.level())
+                    .map(|(i, _)| i);
+                if let Some(i) = quietest {
+                    self.voices.swap_remove(i);
+                }
+            }
+
+            let voice = Voice {
+                id: self.next_id,
+                peak: *peak,
+                envelope: Envelope::new(),
+                held: true,
+                age: 0,
+                allocated: false,
+                allocated_for: 0,
+                channel: self.next_channel,
+            };
+            #[allow(clippy::modulo_one)]
+            let next_channel = (self.next_channel + 1) % config::pulse::OUTPUT_CHANNELS;
+            self.next_channel = next_channel;
+            self.next_id = self.next_id.wrapping_add(1);
+            if let Err(_) = self.voices.push(voice) {
+                defmt::warn!("Voices overflowed (impossible)");
+            }
+        }
+
+        // Step 3: continue released voices at the same frequency and amplitude
+        for voice in &mut self.voices {
+            voice.age = voice.age.saturating_add(1);
+            if !voice.held {
+                voice.peak.advance(config::adc::BUF_DURATION);
+            }
+        }
+
+        // Step 4: choose which voices sound
+        allocator::allocate(&mut self.voices);
+
+        // Step 5: advance envelopes (voices which are not allocated are released)
+        for voice in &mut self.voices {
+            voice.envelope.advance(voice.held && voice.allocated);
+        }
+
+        // Step 6: remove voices which have faded out, and whose peak has disappeared
+        self.voices
+            .retain(|voice| voice.held || !voice.envelope.is_finished());
+    }
+}
+
+pub fn log_voices(voices: &Voices) {
+    for voice in voices.iter() {
+        defmt::println!(
+            "Voice freq = {}, level = {}, held = {}, allocated = {}",
+            voice.peak.freq().to_Hz(),
+            voice.level(),
+            voice.held,
+            voice.allocated,
+        );
+    }
+}
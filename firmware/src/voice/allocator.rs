@@ -0,0 +1,66 @@
+use crate::config;
+use crate::config::pulse::VoicePriority;
+use crate::voice::Voice;
+use heapless::Vec;
+
+/// Number of buffers a newly allocated voice is kept for, regardless of priority.
+const HOLD_BUFFERS: u16 = {
+    let buffers = config::pulse::VOICE_HOLD_TIME.ticks() / config::adc::BUF_DURATION.ticks();
+    #[allow(clippy::cast_possible_truncation)]
+    let buffers = buffers as u16;
+    buffers
+};
+
+/// Choose which held voices are allocated (i.e. sound), up to `POLYPHONY`.
+pub fn allocate(voices: &mut Vec<Voice, { config::pulse::MAX_VOICES }>) {
+    // Step 1: voices which are no longer held are not allocated (but may still be releasing)
+    for voice in voices.iter_mut() {
+        if !voice.held {
+            voice.allocated = false;
+        }
+    }
+
+    // Step 2: keep newly allocated voices until their hold time has passed
+    let mut slots = config::pulse::POLYPHONY;
+    for voice in voices.iter() {
+        if voice.allocated && voice.allocated_for < HOLD_BUFFERS {
+            slots = slots.saturating_sub(1);
+        }
+    }
+
+    // Step 3: rank the remaining held voices by priority
+    let mut candidates: Vec<usize, { config::pulse::MAX_VOICES }> = voices
+        .iter()
+        .enumerate()
+        .filter(|(_, voice)| voice.held && !(voice.allocated && voice.allocated_for < HOLD_BUFFERS))
+        .map(|(i, _)| i)
+        .collect();
+    candidates.sort_unstable_by_key(|&i| priority(&voices[i]));
+
+    // Step 4: allocate the highest-priority voices to the remaining slots
+    for (rank, &i) in candidates.iter().enumerate() {
+        let voice = &mut voices[i];
+        let allocate = rank < slots;
+        if allocate && !voice.allocated {
+            voice.allocated_for = 0;
+        }
+        voice.allocated = allocate;
+    }
+
+    // Step 5: advance hold timers
+    for voice in voices.iter_mut() {
+        if voice.allocated {
+            voice.allocated_for = voice.allocated_for.saturating_add(1);
+        }
+    }
+}
+
+/// Sort key for a voice, with lower values having higher priority.
+fn priority(voice: &Voice) -> u32 {
+    match config::pulse::VOICE_PRIORITY {
+        VoicePriority::Loudest => u32::MAX - u32::from(voice.peak.amplitude()),
+        VoicePriority::Highest => u32::MAX - voice.peak.freq().to_Hz(),
+        VoicePriority::Lowest => voice.peak.freq().to_Hz(),
+        VoicePriority::Oldest => u32::MAX - u32::from(voice.age),
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config;
+use crate::config::pulse::ArpPattern;
+use crate::voice::{Voice, Voices};
+use heapless::Vec;
+
+/// Number of buffers to play each step for.
+const STEP_BUFFERS: u16 = {
+    let buffers = config::pulse::ARP_STEP_TIME.ticks() / config::adc::BUF_DURATION.ticks();
+    #[allow(clippy::cast_possible_truncation)]
+    let buffers = if buffers == 0 { 1 } else { buffers as u16 };
+    buffers
+};
+
+const _: () = assert!(
+    config::pulse::MAX_VOICES <= u32::BITS as usize,
+    "selected voices should fit in a bitmask"
+);
+
+/// Cycles through the voices of a chord, playing `ARP_VOICES` at a time,
+/// when more voices are allocated than that.
+pub struct Arpeggiator {
+    /// Position in the pattern
+    position: usize,
+    /// Number of buffers the current step has been played for
+    buffers_in_step: u16,
+    /// State for `ArpPattern::Random`
+    rng: u32,
+    /// Bitmask of voices to play in the current buffer, by index
+    selected: u32,
+}
+
+impl Arpeggiator {
+    pub const fn new() -> Self {
+        Self {
+            position: 0,
+            buffers_in_step: 0,
+            rng: 0x2545_f491,
+            selected: 0,
+        }
+    }
+
+    /// Advance by one buffer, and choose which voices of the chord to play.
+    ///
+    /// This must be called after `Voices::update`, since voices may be reordered.
+    #[inline(never)]
+    pub fn advance(&mut self, voices: &Voices) {
+        // Step 1: find voices in the chord, from lowest to highest
+        let mut chord: Vec<usize, { config::pulse::MAX_VOICES }> = voices
+            .voices
+            .iter()
+            .enumerate()
+            .filter(|(_, voice)| voice.held && voice.allocated)
+            .map(|(i, _)| i)
+            .collect();
+        chord.sort_unstable_by_key(|&i| voices.voices[i].peak.freq().to_Hz());
+
+        // Step 2: play the whole chord if it's small enough
+        if chord.len() <= config::pulse::ARP_VOICES {
+            self.selected = chord.iter().fold(0, |mask, &i| mask | 1 << i);
+            return;
+        }
+
+        // Step 3: move to the next step
+        self.buffers_in_step += 1;
+        if self.buffers_in_step >= STEP_BUFFERS {
+            self.buffers_in_step = 0;
+            self.position = self.position.wrapping_add(1);
+            if config::pulse::ARP_PATTERN == ArpPattern::Random {
+                // xorshift32
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 17;
+                self.rng ^= self.rng << 5;
+            }
+        }
+
+        // Step 4: select voices for the current step
+        let n = chord.len();
+        let start = match config::pulse::ARP_PATTERN {
+            ArpPattern::Up => self.position % n,
+            ArpPattern::Down => n - 1 - self.position % n,
+            ArpPattern::UpDown => {
+                let period = 2 * n - 2;
+                let p = self.position % period;
+                if p < n {
+                    p
+                } else {
+                    period - p
+                }
+            }
+            ArpPattern::Random => self.rng as usize % n,
+        };
+        self.selected = (0..config::pulse::ARP_VOICES)
+            .map(|k| chord[(start + k) % n])
+            .fold(0, |mask, i| mask | 1 << i);
+    }
+
+    /// Voices to play in the current buffer.
+    ///
+    /// This includes voices which are releasing, since they are no longer part of the chord.
+    pub fn playing<'a>(&'a self, voices: &'a Voices) -> impl Iterator<Item = &'a Voice> {
+        voices
+            .voices
+            .iter()
+            .enumerate()
+            .filter(|(i, voice)| !(voice.held && voice.allocated) || self.selected & 1 << i != 0)
+            .map(|(_, voice)| voice)
+            .filter(|voice| voice.is_sounding())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config;
+use crate::math::{const_scale_by_u16_u16, ScalingFactor};
+use crate::time::Duration;
+use defmt::Format;
+
+const SUSTAIN_LEVEL: u16 = const_scale_by_u16_u16(u16::MAX, config::pulse::SUSTAIN);
+
+const ATTACK_STEP: u16 = step(u16::MAX, config::pulse::ATTACK);
+const DECAY_STEP: u16 = step(u16::MAX - SUSTAIN_LEVEL, config::pulse::DECAY);
+const RELEASE_STEP: u16 = step(u16::MAX, config::pulse::RELEASE);
+
+/// Change in level per buffer, to cover `range` over `duration`.
+///
+/// The duration is rounded to the nearest number of buffers (at least 1),
+/// and the step rounded up, so `range` is covered in exactly that many buffers.
+const fn step(range: u16, duration: Duration) -> u16 {
+    let buf = config::adc::BUF_DURATION.ticks();
+    let buffers = (duration.ticks() + buf / 2) / buf;
+    let buffers = if buffers == 0 { 1 } else { buffers };
+    #[allow(clippy::cast_possible_truncation)]
+    let step = (range as u32).div_ceil(buffers) as u16;
+    step
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Format)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// ADSR envelope for a voice, advanced once per buffer.
+pub struct Envelope {
+    stage: Stage,
+    level: u16,
+}
+
+impl Envelope {
+    pub const fn new() -> Self {
+        Self {
+            stage: Stage::Attack,
+            level: 0,
+        }
+    }
+
+    /// Advance the envelope by one buffer.
+    ///
+    /// `held` indicates whether the voice's peak is present in this buffer.
+    /// If a released voice is held again, it attacks again from its current level.
+    pub fn advance(&mut self, held: bool) {
+        self.stage = match (held, self.stage) {
+            (false, _) => Stage::Release,
+            (true, Stage::Release) => Stage::Attack,
+            (true, stage) => stage,
+        };
+
+        match self.stage {
+            Stage::Attack => {
+                self.level = self.level.saturating_add(ATTACK_STEP);
+                if self.level == u16::MAX {
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level = self.level.saturating_sub(DECAY_STEP).max(SUSTAIN_LEVEL);
+                if self.level == SUSTAIN_LEVEL {
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level = self.level.saturating_sub(RELEASE_STEP);
+            }
+        }
+    }
+
+    pub fn level(&self) -> ScalingFactor<u16> {
+        ScalingFactor::from_raw(self.level)
+    }
+
+    /// Whether the voice has been released and faded out completely.
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Release && self.level == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of buffers taken to cover `range` in steps of `step`.
+    fn buffers(range: u16, step: u16) -> u32 {
+        u32::from(range).div_ceil(u32::from(step))
+    }
+
+    #[test]
+    fn step_rounds_to_nearest_buffer() {
+        let buf = config::adc::BUF_DURATION;
+        assert_eq!(buffers(u16::MAX, step(u16::MAX, buf * 4)), 4);
+        assert_eq!(buffers(u16::MAX, step(u16::MAX, buf * 4 + buf / 3)), 4);
+        assert_eq!(buffers(u16::MAX, step(u16::MAX, buf * 4 + buf * 2 / 3)), 5);
+        assert_eq!(buffers(u16::MAX, step(u16::MAX, buf * 5 - buf / 3)), 5);
+    }
+
+    #[test]
+    fn step_covers_range_in_at_least_one_buffer() {
+        let buf = config::adc::BUF_DURATION;
+        assert_eq!(step(u16::MAX, Duration::from_ticks(0)), u16::MAX);
+        assert_eq!(step(u16::MAX, buf / 3), u16::MAX);
+        assert_eq!(step(u16::MAX, buf * 2 / 3), u16::MAX);
+        assert_eq!(step(1000, buf), 1000);
+    }
+
+    #[test]
+    fn step_is_at_least_one() {
+        let buf = config::adc::BUF_DURATION;
+        assert_eq!(step(10, buf * 100), 1);
+        // unless there's nothing to cover
+        assert_eq!(step(0, buf * 10), 0);
+    }
+
+    #[test]
+    fn attack_reaches_full_level() {
+        let mut envelope = Envelope::new();
+        for _ in 0..buffers(u16::MAX, ATTACK_STEP) {
+            envelope.advance(true);
+        }
+        assert_eq!(envelope.level, u16::MAX);
+        assert!(envelope.stage != Stage::Attack);
+    }
+
+    #[test]
+    fn release_finishes() {
+        let mut envelope = Envelope::new();
+        envelope.level = u16::MAX;
+        envelope.stage = Stage::Sustain;
+        for _ in 0..buffers(u16::MAX, RELEASE_STEP) {
+            assert!(!envelope.is_finished());
+            envelope.advance(false);
+        }
+        assert!(envelope.is_finished());
+    }
+}
@@ -27,9 +27,11 @@ All available commands:
 .vz <id> yr <y-axis min> <y-axis max>
 .vz <id> xs [<x coord>, ...]
 .vz <id> ys [<y coord>, ...]
+.vz st <name> <value>
 
 Note: `xr`/`yr` will automatically fit the data.
 Note: `xs` doesn't need to be resent every time.
+Note: `st` sets an entry in the status panel, which is shown next to the log.
 
 Example sequence:
 .vz cn 0 FFT
@@ -39,6 +39,13 @@ pub fn handle_line(state: &mut State, line: &str) -> Redraw {
         // Parse prefix
         let Some(line) = line.strip_prefix(".vz ") else { return Err(ParseError::NotACommand) };
 
+        // Status entries don't belong to a chart
+        if let Some(args) = line.strip_prefix("st ") {
+            let Some((name, value)) = args.rsplit_once(' ') else { return Err(ParseError::MissingArgument) };
+            state.set_status(name, value);
+            return Ok(Redraw::Yes);
+        }
+
         // Parse chart id
         let Some((chart_id, line)) = line.split_once(' ') else { return Err(ParseError::MissingChartId) };
         let chart_id = chart_id.parse()?;
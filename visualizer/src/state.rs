@@ -4,6 +4,7 @@ use std::fmt::Write;
 
 pub struct State {
     charts: Vec<(i32, Chart)>,
+    status: Vec<(String, String)>,
     logs: VecDeque<String>,
 }
 
@@ -11,6 +12,7 @@ impl Default for State {
     fn default() -> Self {
         Self {
             charts: Vec::new(),
+            status: Vec::new(),
             logs: VecDeque::with_capacity(config::SCROLLBACK_LINES),
         }
     }
@@ -36,6 +38,24 @@ impl State {
         }
     }
 
+    pub fn status(&self) -> &[(String, String)] {
+        &self.status
+    }
+
+    pub fn set_status(&mut self, name: &str, value: &str) {
+        match self.status.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing_value)) => {
+                // Update existing entry in place, keeping its position
+                existing_value.clear();
+                existing_value.push_str(value);
+            }
+            None => {
+                // Append new entry
+                self.status.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+
     pub fn logs(&self) -> &VecDeque<String> {
         &self.logs
     }
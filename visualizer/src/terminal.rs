@@ -24,7 +24,18 @@ pub fn draw(state: &State, terminal: &mut Terminal<impl Backend>) -> Result<(),
             .split(f.size());
 
         draw_charts(state, f, chunks[0]);
-        draw_log(state, f, chunks[1]);
+
+        if state.status().is_empty() {
+            draw_log(state, f, chunks[1]);
+        } else {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Ratio(3, 4), Constraint::Ratio(1, 4)])
+                .split(chunks[1]);
+
+            draw_log(state, f, chunks[0]);
+            draw_status(state, f, chunks[1]);
+        }
     })?;
 
     Ok(())
@@ -137,3 +148,45 @@ fn draw_log(state: &State, f: &mut Frame<impl Backend>, area: Rect) {
 
     f.render_widget(LogWidget { logs: state.logs() }, area);
 }
+
+fn draw_status(state: &State, f: &mut Frame<impl Backend>, area: Rect) {
+    struct StatusWidget<'a> {
+        status: &'a [(String, String)],
+    }
+
+    impl<'a> Widget for StatusWidget<'a> {
+        fn render(self, area: Rect, buf: &mut Buffer) {
+            let block = Block::default()
+                .title(Span::styled(
+                    "Status",
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD),
+                ))
+                .borders(Borders::ALL);
+
+            let area = {
+                let a = block.inner(area);
+                block.render(area, buf);
+                a
+            };
+
+            for (i, (name, value)) in self.status.iter().take(area.height as usize).enumerate() {
+                let y = area.top() + i as u16;
+                buf.set_string(area.left(), y, name, Style::default().fg(Color::Gray));
+                let x = area
+                    .right()
+                    .saturating_sub(value.len() as u16)
+                    .max(area.left());
+                buf.set_string(x, y, value, Style::default().add_modifier(Modifier::BOLD));
+            }
+        }
+    }
+
+    f.render_widget(
+        StatusWidget {
+            status: state.status(),
+        },
+        area,
+    );
+}